@@ -0,0 +1,410 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use snap::read::FrameDecoder;
+
+use crate::error::Error;
+use crate::log::{clean_log_handles, create_log_file_handles, read_and_compress_log_files};
+use crate::state::State;
+use crate::task::Task;
+
+/// Return the path to the archive file inside the pueue directory.
+pub fn get_archive_path(path: &Path) -> PathBuf {
+    path.join("task_archive.cbor")
+}
+
+/// The archive is first written to this file and then moved to its actual path.
+/// That way, the archive isn't corrupted, if the process dies while writing.
+fn get_temporary_archive_path(path: &Path) -> PathBuf {
+    path.join("task_archive.cbor.partial")
+}
+
+/// Query the archive of cleaned tasks.
+///
+/// `task_ids` filters by the original task ids. If none are given, all tasks are selected.
+/// `group` Only return tasks of this group.
+/// `failed_only` Only return tasks that didn't finish successfully.
+/// `limit` The maximum amount of tasks, starting with the most recently archived ones.
+/// `send_logs` Determines whether archived logs should be sent as well.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct ArchiveQueryMessage {
+    pub task_ids: Vec<usize>,
+    pub group: Option<String>,
+    pub failed_only: bool,
+    pub limit: Option<usize>,
+    pub send_logs: bool,
+}
+
+/// A task that has been removed from the state and moved to the [Archive].
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct ArchivedTask {
+    /// The unique id of this entry inside the archive. \
+    /// Task ids are reused by the daemon after cleaning, which is why they cannot be used.
+    pub archive_id: usize,
+    pub archived_at: DateTime<Local>,
+    pub task: Task,
+    /// The task's [snap] compressed stdout, if logs have been archived.
+    pub stdout: Option<Vec<u8>>,
+    /// The task's [snap] compressed stderr, if logs have been archived.
+    pub stderr: Option<Vec<u8>>,
+    /// The reason why the logs are missing, although they should have been archived.
+    #[serde(default)]
+    pub log_error: Option<String>,
+}
+
+impl ArchivedTask {
+    /// Decompress the archived logs and write them as log files of the given task id. \
+    /// This is used to restore the logs, when a task is moved back into the state.
+    pub fn restore_logs(&self, task_id: usize, path: &Path) -> Result<(), Error> {
+        let (mut stdout_file, mut stderr_file) = create_log_file_handles(task_id, path)?;
+        if let Some(stdout) = &self.stdout {
+            io::copy(
+                &mut FrameDecoder::new(Cursor::new(stdout)),
+                &mut stdout_file,
+            )?;
+        }
+        if let Some(stderr) = &self.stderr {
+            io::copy(
+                &mut FrameDecoder::new(Cursor::new(stderr)),
+                &mut stderr_file,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// The size of the compressed logs in bytes.
+    pub fn log_size(&self) -> usize {
+        self.stdout.as_ref().map_or(0, Vec::len) + self.stderr.as_ref().map_or(0, Vec::len)
+    }
+}
+
+/// The history of cleaned tasks.
+///
+/// The archive is persisted in its own file next to the state. \
+/// It's bounded by `max_tasks`. Once it's full, the oldest entries are dropped. \
+/// The size of all archived logs is bounded by `max_log_size` bytes. Once it's exceeded,
+/// the logs of the oldest entries are dropped, while the entries themselves are kept.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct Archive {
+    pub max_tasks: usize,
+    #[serde(default)]
+    pub max_log_size: usize,
+    next_id: usize,
+    tasks: VecDeque<ArchivedTask>,
+}
+
+impl Archive {
+    /// Create a new empty archive.
+    pub fn new(max_tasks: usize, max_log_size: usize) -> Archive {
+        Archive {
+            max_tasks,
+            max_log_size,
+            next_id: 0,
+            tasks: VecDeque::new(),
+        }
+    }
+
+    /// Read the archive from the given pueue directory. \
+    /// If no archive exists yet, a new empty archive is returned.
+    pub fn read(path: &Path, max_tasks: usize, max_log_size: usize) -> Result<Archive, Error> {
+        let archive_path = get_archive_path(path);
+        if !archive_path.exists() {
+            return Ok(Archive::new(max_tasks, max_log_size));
+        }
+
+        let file = File::open(archive_path)?;
+        let mut archive: Archive =
+            serde_cbor::from_reader(file).map_err(|err| Error::ArchiveRead(err.to_string()))?;
+
+        // The limits might have been changed in the meantime.
+        archive.max_tasks = max_tasks;
+        archive.max_log_size = max_log_size;
+        archive.truncate();
+
+        Ok(archive)
+    }
+
+    /// Write the archive to the given pueue directory. \
+    /// The archive is written to a temporary file first, which then replaces the old archive.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let temporary_path = get_temporary_archive_path(path);
+        let file = File::create(&temporary_path)?;
+        serde_cbor::to_writer(file, self).map_err(|err| Error::ArchiveWrite(err.to_string()))?;
+        fs::rename(temporary_path, get_archive_path(path))?;
+
+        Ok(())
+    }
+
+    /// Move a task into the archive. \
+    /// If `with_logs` is set, the task's log files are compressed and stored alongside.
+    /// The log files themselves are left untouched. If they cannot be read, the task is
+    /// archived without logs and the error is recorded in [ArchivedTask::log_error].
+    ///
+    /// Returns the id of the new archive entry.
+    pub fn archive_task(&mut self, task: Task, with_logs: bool, path: &Path) -> usize {
+        let (stdout, stderr, log_error) = if with_logs {
+            match read_and_compress_log_files(task.id, path, None) {
+                Ok((stdout, stderr)) => (Some(stdout), Some(stderr), None),
+                Err(err) => (None, None, Some(format!("Failed to read logs: {}", err))),
+            }
+        } else {
+            (None, None, None)
+        };
+
+        let archive_id = self.next_id;
+        self.next_id += 1;
+        self.tasks.push_back(ArchivedTask {
+            archive_id,
+            archived_at: Local::now(),
+            task,
+            stdout,
+            stderr,
+            log_error,
+        });
+        self.truncate();
+
+        archive_id
+    }
+
+    /// Get all archived tasks that match the query, newest entries first.
+    pub fn query(&self, query: &ArchiveQueryMessage) -> Vec<ArchivedTask> {
+        let matches = self.tasks.iter().rev().filter(|archived| {
+            let task = &archived.task;
+            if !query.task_ids.is_empty() && !query.task_ids.contains(&task.id) {
+                return false;
+            }
+            if let Some(group) = &query.group {
+                if &task.group != group {
+                    return false;
+                }
+            }
+            !query.failed_only || task.failed()
+        });
+
+        matches
+            .take(query.limit.unwrap_or(usize::MAX))
+            .map(|archived| {
+                let mut archived = archived.clone();
+                if !query.send_logs {
+                    archived.stdout = None;
+                    archived.stderr = None;
+                }
+                archived
+            })
+            .collect()
+    }
+
+    /// Get a single archived task.
+    pub fn get(&self, archive_id: usize) -> Option<&ArchivedTask> {
+        self.tasks
+            .iter()
+            .find(|archived| archived.archive_id == archive_id)
+    }
+
+    /// Remove an entry from the archive, e.g. to restore it to the state.
+    pub fn take(&mut self, archive_id: usize) -> Option<ArchivedTask> {
+        let index = self
+            .tasks
+            .iter()
+            .position(|archived| archived.archive_id == archive_id)?;
+
+        self.tasks.remove(index)
+    }
+
+    /// Move an archived task back into the state. \
+    /// The task gets a new id, as its old id might already be taken. Its dependencies are
+    /// removed for the same reason. If its group no longer exists, the default group is used.
+    /// Archived logs are written to the log files of the new task id.
+    ///
+    /// The entry is only removed from the archive, once the task and its logs have been
+    /// restored. If the logs cannot be restored, the task isn't added to the state either.
+    ///
+    /// Returns the new id of the restored task.
+    pub fn restore(
+        &mut self,
+        archive_id: usize,
+        state: &mut State,
+        path: &Path,
+    ) -> Result<usize, Error> {
+        let archived = self.get(archive_id).ok_or_else(|| {
+            Error::Generic(format!("Archive entry {} doesn't exist.", archive_id))
+        })?;
+
+        let mut task = archived.task.clone();
+        task.dependencies = Vec::new();
+        if !state.groups.contains_key(&task.group) {
+            task.set_default_group();
+        }
+        let task_id = state.add_task(task);
+
+        if archived.stdout.is_some() || archived.stderr.is_some() {
+            if let Err(err) = archived.restore_logs(task_id, path) {
+                // Don't leave partially restored logs behind.
+                clean_log_handles(task_id, path);
+                state.tasks.remove(&task_id);
+                return Err(err);
+            }
+        }
+        self.take(archive_id);
+
+        Ok(task_id)
+    }
+
+    /// The amount of archived tasks.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// The size of all archived logs in bytes.
+    pub fn log_size(&self) -> usize {
+        self.tasks.iter().map(ArchivedTask::log_size).sum()
+    }
+
+    /// Drop the oldest entries until the archive fits into its limit.
+    /// Afterwards, drop the logs of the oldest entries until they fit into their limit.
+    fn truncate(&mut self) {
+        while self.tasks.len() > self.max_tasks {
+            self.tasks.pop_front();
+        }
+
+        let mut log_size = self.log_size();
+        for archived in self.tasks.iter_mut() {
+            if log_size <= self.max_log_size {
+                break;
+            }
+            if archived.log_size() == 0 {
+                continue;
+            }
+
+            log_size -= archived.log_size();
+            archived.stdout = None;
+            archived.stderr = None;
+            archived.log_error = Some("Logs have been dropped to limit the archive's size".into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    use crate::settings::Settings;
+    use crate::state::PUEUE_DEFAULT_GROUP;
+    use crate::task::{TaskResult, TaskStatus};
+    use crate::test_helper;
+
+    fn finished_task(result: TaskResult) -> Task {
+        let mut task = test_helper::task(PUEUE_DEFAULT_GROUP, TaskStatus::Done(result));
+        task.dependencies = vec![0];
+
+        task
+    }
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let tempdir = TempDir::new("pueue_lib").unwrap();
+        let path = tempdir.path();
+        std::fs::create_dir(path.join("task_logs")).unwrap();
+
+        let mut archive = Archive::new(2, 0);
+        for result in [
+            TaskResult::Success,
+            TaskResult::Failed(1),
+            TaskResult::Killed,
+        ] {
+            archive.archive_task(finished_task(result), false, path);
+        }
+        // The oldest task has been dropped.
+        assert_eq!(archive.len(), 2);
+        assert!(archive.get(0).is_none());
+
+        archive.save(path).unwrap();
+        let mut archive = Archive::read(path, 2, 0).unwrap();
+
+        let query = ArchiveQueryMessage {
+            task_ids: Vec::new(),
+            group: None,
+            failed_only: true,
+            limit: Some(1),
+            send_logs: false,
+        };
+        let result = archive.query(&query);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].archive_id, 2);
+
+        let mut state = State::new(&Settings::read_with_defaults(&None).unwrap().0, None);
+        let task_id = archive.restore(1, &mut state, path).unwrap();
+        assert_eq!(archive.len(), 1);
+        assert_eq!(
+            state.tasks[&task_id].status,
+            TaskStatus::Done(TaskResult::Failed(1))
+        );
+        assert!(state.tasks[&task_id].dependencies.is_empty());
+    }
+
+    #[test]
+    fn test_archive_logs() {
+        let tempdir = TempDir::new("pueue_lib").unwrap();
+        let path = tempdir.path();
+        std::fs::create_dir(path.join("task_logs")).unwrap();
+        let (out_path, err_path) = crate::log::get_log_paths(0, path);
+        std::fs::write(out_path, "output\n").unwrap();
+        std::fs::write(err_path, "").unwrap();
+
+        let mut archive = Archive::new(10, usize::MAX);
+        archive.archive_task(finished_task(TaskResult::Success), true, path);
+        let log_size = archive.log_size();
+        assert!(log_size > 0);
+
+        // Missing log files are recorded.
+        let mut task = finished_task(TaskResult::Success);
+        task.id = 1;
+        archive.archive_task(task, true, path);
+        assert!(archive.get(1).unwrap().log_error.is_some());
+
+        // The logs of the oldest entries are dropped first.
+        archive.max_log_size = log_size;
+        archive.archive_task(finished_task(TaskResult::Success), true, path);
+        assert_eq!(archive.len(), 3);
+        assert_eq!(archive.log_size(), log_size);
+        assert!(archive.get(0).unwrap().stdout.is_none());
+        assert!(archive.get(0).unwrap().log_error.is_some());
+        assert!(archive.get(2).unwrap().stdout.is_some());
+
+        // A failed restore neither loses the entry nor adds the task.
+        std::fs::remove_dir_all(path.join("task_logs")).unwrap();
+        let mut state = State::new(&Settings::read_with_defaults(&None).unwrap().0, None);
+        assert!(archive.restore(2, &mut state, path).is_err());
+        assert!(archive.get(2).is_some());
+        assert!(state.tasks.is_empty());
+
+        archive.save(path).unwrap();
+        assert!(!get_temporary_archive_path(path).exists());
+        assert_eq!(Archive::read(path, 10, log_size).unwrap(), archive);
+
+        // Logs that couldn't be restored completely are removed again.
+        std::fs::create_dir(path.join("task_logs")).unwrap();
+        let index = archive
+            .tasks
+            .iter()
+            .position(|archived| archived.archive_id == 2);
+        archive.tasks[index.unwrap()].stderr = Some(b"no snap frame".to_vec());
+        assert!(archive.restore(2, &mut state, path).is_err());
+        let (out_path, err_path) = crate::log::get_log_paths(0, path);
+        assert!(!out_path.exists());
+        assert!(!err_path.exists());
+        assert!(state.tasks.is_empty());
+    }
+}
@@ -1,3 +1,6 @@
+use serde_derive::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Couldn't find or open file: {}", .0)]
@@ -16,12 +19,52 @@ pub enum Error {
     #[error("Got an empty payload")]
     EmptyPayload,
 
+    /// A message frame exceeds the maximum frame size.
+    /// This is either a malformed or malicious header or a message that should've been chunked.
+    #[error("Message of {} bytes exceeds the maximum frame size of {} bytes", .size, .max)]
+    FrameTooLarge { size: usize, max: usize },
+
     #[error("Couldn't deserialize message:\n{}", .0)]
     MessageDeserialization(String),
 
     #[error("Couldn't serialize message:\n{}", .0)]
     MessageSerialization(String),
 
+    /// The other side of the connection speaks an incompatible protocol version.
+    #[error(
+        "Incompatible protocol versions. We speak {}-{}, the other side speaks {}-{}.",
+        .local_min, .local, .remote_min, .remote
+    )]
+    IncompatibleProtocol {
+        local: u32,
+        local_min: u32,
+        remote: u32,
+        remote_min: u32,
+    },
+
+    /// The other side of the connection didn't answer with a valid handshake.
+    /// It most likely runs a version that predates the handshake. \
+    /// `remote_version` is the version the daemon sent before the handshake, if it's known.
+    #[error(
+        "Incompatible protocol. The other side{} doesn't support the protocol handshake, it probably runs an older version.",
+        .remote_version.as_ref().map(|version| format!(" (version {})", version)).unwrap_or_default()
+    )]
+    MissingHandshake { remote_version: Option<String> },
+
+    /// The other side of the connection doesn't support a feature that's needed for a message.
+    #[error("The other side doesn't support the '{}' feature.", .0)]
+    UnsupportedFeature(String),
+
+    /// The daemon rejected a request. \
+    /// Plain [Message::Failure](crate::network::message::Message::Failure)s use
+    /// [ErrorCode::Other].
+    #[error("{}", .0.text)]
+    Failure(FailureMessage),
+
+    /// The daemon answered with a message that doesn't fit the request.
+    #[error("Received unexpected response: {}", .0)]
+    UnexpectedResponse(String),
+
     #[error("Failed while building configuration.")]
     ConfigError(#[from] config::ConfigError),
 
@@ -34,6 +77,12 @@ pub enum Error {
     #[error("Couldn't read task log file. {}", .0)]
     LogRead(String),
 
+    #[error("Couldn't write task archive. {}", .0)]
+    ArchiveWrite(String),
+
+    #[error("Couldn't read task archive. {}", .0)]
+    ArchiveRead(String),
+
     #[error("Some error occurred. {}", .0)]
     Generic(String),
 
@@ -49,3 +98,54 @@ pub enum Error {
     )]
     UnixSocketExists,
 }
+
+/// The reasons why a request, or a part of it, failed.
+#[derive(PartialEq, Clone, Copy, Debug, Deserialize, Serialize, Display, EnumString)]
+pub enum ErrorCode {
+    #[strum(serialize = "TaskNotFound", serialize = "task_not_found")]
+    TaskNotFound,
+    #[strum(serialize = "GroupNotFound", serialize = "group_not_found")]
+    GroupNotFound,
+    #[strum(serialize = "GroupExists", serialize = "group_exists")]
+    GroupExists,
+    /// The task is running or paused, but the request needs a task that isn't.
+    #[strum(serialize = "TaskRunning", serialize = "task_running")]
+    TaskRunning,
+    /// The task isn't running or paused, but the request needs a task that is.
+    #[strum(serialize = "TaskNotRunning", serialize = "task_not_running")]
+    TaskNotRunning,
+    /// The task has any other status that doesn't allow the request, e.g. it's already queued.
+    #[strum(serialize = "InvalidTaskStatus", serialize = "invalid_task_status")]
+    InvalidTaskStatus,
+    /// A dependency doesn't exist or would create a cycle.
+    #[strum(serialize = "InvalidDependency", serialize = "invalid_dependency")]
+    InvalidDependency,
+    /// The request itself is malformed, e.g. an invalid batch.
+    #[strum(serialize = "InvalidRequest", serialize = "invalid_request")]
+    InvalidRequest,
+    /// A task finished without reaching the condition that has been waited for.
+    #[strum(
+        serialize = "ConditionUnreachable",
+        serialize = "condition_unreachable"
+    )]
+    ConditionUnreachable,
+    /// The request timed out, before it could be applied to the task.
+    #[strum(serialize = "Timeout", serialize = "timeout")]
+    Timeout,
+    /// The daemon failed to access its files, e.g. logs.
+    #[strum(serialize = "Io", serialize = "io")]
+    Io,
+    #[strum(serialize = "Other", serialize = "other")]
+    Other,
+}
+
+/// A failure with a machine readable `code`.
+///
+/// `task_ids` are the tasks that are affected by this failure, if any. \
+/// `text` is a human readable description of the failure.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct FailureMessage {
+    pub code: ErrorCode,
+    pub task_ids: Vec<usize>,
+    pub text: String,
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::task::{Task, TaskResult, TaskStatus};
+
+/// A serializable representation of the dependency graph of tasks. \
+/// Its JSON representation is meant to be consumed by third-party tools.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct TaskGraph {
+    pub nodes: Vec<TaskNode>,
+    pub edges: Vec<TaskEdge>,
+}
+
+/// A single task inside the [TaskGraph].
+///
+/// `status` is the name of the task's [TaskStatus]. \
+/// `result` is the name of the task's [TaskResult], if the task finished. \
+/// `exit_code` is the exit code of the task's process, if it exited on its own.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct TaskNode {
+    pub id: usize,
+    pub label: Option<String>,
+    pub command: String,
+    pub group: String,
+    pub status: String,
+    pub result: Option<String>,
+    pub exit_code: Option<i32>,
+}
+
+/// A dependency between two tasks. \
+/// The task `to` won't be started until the task `from` finished successfully.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct TaskEdge {
+    pub from: usize,
+    pub to: usize,
+}
+
+impl TaskGraph {
+    /// Build the dependency graph of the given tasks. \
+    /// Dependencies on tasks that aren't part of the given tasks are ignored.
+    pub fn from_tasks(tasks: &BTreeMap<usize, Task>) -> TaskGraph {
+        let nodes = tasks
+            .values()
+            .map(|task| TaskNode {
+                id: task.id,
+                label: task.label.clone(),
+                command: task.command.clone(),
+                group: task.group.clone(),
+                status: task.status.to_string(),
+                result: match &task.status {
+                    TaskStatus::Done(result) => Some(result.to_string()),
+                    _ => None,
+                },
+                exit_code: match &task.status {
+                    TaskStatus::Done(TaskResult::Success) => Some(0),
+                    TaskStatus::Done(TaskResult::Failed(code)) => Some(*code),
+                    _ => None,
+                },
+            })
+            .collect();
+
+        let edges = tasks
+            .values()
+            .flat_map(|task| {
+                task.dependencies
+                    .iter()
+                    .filter(|dependency| tasks.contains_key(dependency))
+                    .map(move |dependency| TaskEdge {
+                        from: *dependency,
+                        to: task.id,
+                    })
+            })
+            .collect();
+
+        TaskGraph { nodes, edges }
+    }
+
+    /// Serialize the graph to JSON.
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).map_err(|err| Error::Generic(err.to_string()))
+    }
+}
+
+/// Render the dependency graph of the given tasks in the Graphviz DOT format. \
+/// Nodes are labeled with the task's id, label, command and result and colored by their status.
+pub fn tasks_to_dot(tasks: &BTreeMap<usize, Task>) -> String {
+    let mut dot = String::from("digraph tasks {\n    node [shape=box, style=filled];\n");
+
+    for task in tasks.values() {
+        let mut label = task.id.to_string();
+        if let Some(task_label) = &task.label {
+            label.push_str(&format!(" ({})", task_label));
+        }
+        label.push('\n');
+        label.push_str(&task.command);
+        match &task.status {
+            TaskStatus::Done(TaskResult::Failed(code)) => {
+                label.push_str(&format!("\nFailed with exit code {}", code))
+            }
+            TaskStatus::Done(result) => label.push_str(&format!("\n{}", result)),
+            _ => (),
+        }
+
+        writeln!(
+            dot,
+            "    {} [label=\"{}\", fillcolor=\"{}\"];",
+            task.id,
+            escape_dot(&label),
+            status_color(&task.status)
+        )
+        .unwrap();
+    }
+
+    for edge in TaskGraph::from_tasks(tasks).edges {
+        writeln!(dot, "    {} -> {};", edge.from, edge.to).unwrap();
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+/// The fill color of a task node in the DOT graph.
+fn status_color(status: &TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::Queued => "lightgrey",
+        TaskStatus::Stashed { .. } => "grey",
+        TaskStatus::Running => "lightblue",
+        TaskStatus::Paused => "yellow",
+        TaskStatus::Locked => "orange",
+        TaskStatus::Done(TaskResult::Success) => "green",
+        TaskStatus::Done(_) => "red",
+    }
+}
+
+/// Escape a string, so it can be used inside a quoted DOT string.
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::state::PUEUE_DEFAULT_GROUP;
+    use crate::test_helper;
+
+    fn get_tasks() -> BTreeMap<usize, Task> {
+        let mut tasks = BTreeMap::new();
+        for (id, dependencies) in [(0, vec![]), (1, vec![0, 5])] {
+            let mut task = test_helper::task(PUEUE_DEFAULT_GROUP, TaskStatus::Queued);
+            task.id = id;
+            task.command = "echo \"test\"".into();
+            task.dependencies = dependencies;
+            tasks.insert(id, task);
+        }
+        tasks.get_mut(&0).unwrap().status = TaskStatus::Done(TaskResult::Failed(1));
+
+        tasks
+    }
+
+    #[test]
+    fn test_task_graph() {
+        let graph = TaskGraph::from_tasks(&get_tasks());
+
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(graph.nodes[0].status, "Done");
+        assert_eq!(graph.nodes[0].result, Some("Failed".to_string()));
+        assert_eq!(graph.nodes[0].exit_code, Some(1));
+        assert_eq!(graph.nodes[1].exit_code, None);
+        // The dependency on the non-existing task 5 is ignored.
+        assert_eq!(graph.edges, vec![TaskEdge { from: 0, to: 1 }]);
+    }
+
+    #[test]
+    fn test_tasks_to_dot() {
+        let dot = tasks_to_dot(&get_tasks());
+
+        assert!(dot.contains(
+            "0 [label=\"0\\necho \\\"test\\\"\\nFailed with exit code 1\", fillcolor=\"red\"];"
+        ));
+        assert!(dot.contains("1 [label=\"1\\necho \\\"test\\\"\", fillcolor=\"lightgrey\"];"));
+        assert!(dot.contains("0 -> 1;"));
+    }
+}
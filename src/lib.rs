@@ -12,8 +12,12 @@
 
 /// Contains helper for command aliasing. This will most likely be not interesting for you.
 pub mod aliasing;
+/// The history of cleaned tasks, which is persisted next to the state.
+pub mod archive;
 /// Pueue lib's own Error implementation.
 pub mod error;
+/// Export the dependency graph of tasks to Graphviz DOT or JSON.
+pub mod graph;
 /// Helper classes to read and write log files of Pueue's tasks.
 pub mod log;
 /// Everything you need to communicate with either the daemon or the client.
@@ -26,3 +30,6 @@ pub mod settings;
 pub mod state;
 /// Everything regarding Pueue's task
 pub mod task;
+/// Fixtures, which are shared by the unit tests.
+#[cfg(test)]
+mod test_helper;
@@ -1,12 +1,20 @@
 use std::fs::{read_dir, remove_file, File};
-use std::io::{self, BufReader, Cursor};
+use std::io::{self, BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
 use log::error;
+use serde_derive::{Deserialize, Serialize};
 use snap::write::FrameEncoder;
 
 use crate::error::Error;
 
+/// The output of a task.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum OutputKind {
+    Stdout,
+    Stderr,
+}
+
 /// Return the paths to the `(stdout, stderr)` log files of a task.
 pub fn get_log_paths(task_id: usize, path: &Path) -> (PathBuf, PathBuf) {
     let task_log_dir = path.join("task_logs");
@@ -89,6 +97,33 @@ pub fn read_and_compress_log_files(
     Ok((stdout, stderr))
 }
 
+/// Read the raw output of a task, starting at the byte `offset`. \
+/// At most `max` bytes are read. Output that hasn't been written yet is empty.
+pub fn read_log_file_from(
+    task_id: usize,
+    path: &Path,
+    kind: OutputKind,
+    offset: u64,
+    max: usize,
+) -> Result<Vec<u8>, Error> {
+    let (out_path, err_path) = get_log_paths(task_id, path);
+    let path = match kind {
+        OutputKind::Stdout => out_path,
+        OutputKind::Stderr => err_path,
+    };
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut output = Vec::new();
+    file.take(max as u64).read_to_end(&mut output)?;
+
+    Ok(output)
+}
+
 /// Return the last lines of `(stdout, stderr)` of a task. \
 /// This output is uncompressed and may take a lot of memory, which is why we only read
 /// the last few lines.
@@ -0,0 +1,366 @@
+use std::collections::{BTreeMap, HashMap};
+
+use log::debug;
+
+use crate::error::Error;
+use crate::network::blocking::protocol::*;
+use crate::network::message::*;
+use crate::network::protocol::{with_remote_version, Capabilities};
+use crate::network::requests::*;
+use crate::network::secret::read_shared_secret;
+use crate::settings::Shared;
+use crate::state::State;
+
+/// A high-level client, which talks to the daemon via typed requests and blocks on each of them.
+///
+/// This offers the same operations as the async client.
+/// Failure responses of the daemon are returned as [Error::Failure] and responses that don't fit
+/// the request as [Error::UnexpectedResponse].
+pub struct Client {
+    stream: GenericStream,
+    capabilities: Capabilities,
+    daemon_version: String,
+}
+
+impl Client {
+    /// Connect to the daemon with the given settings.
+    pub fn connect(settings: &Shared) -> Result<Client, Error> {
+        let mut stream = get_client_stream(settings)?;
+
+        // The daemon answers the secret with its version.
+        let secret = read_shared_secret(&settings.shared_secret_path())?;
+        send_bytes(&secret, &mut stream)?;
+        let version_bytes = receive_bytes(&mut stream)?;
+        let daemon_version = daemon_version(version_bytes)?;
+
+        let capabilities = client_handshake(&mut stream)
+            .map_err(|err| with_remote_version(err, &daemon_version))?;
+        debug!(
+            "Connected to daemon {} with {:?}",
+            daemon_version, capabilities
+        );
+
+        Ok(Client {
+            stream,
+            capabilities,
+            daemon_version,
+        })
+    }
+
+    /// The version of the daemon we're connected to.
+    pub fn daemon_version(&self) -> &str {
+        &self.daemon_version
+    }
+
+    /// The capabilities that have been negotiated with the daemon.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Change the maximum size of a single message frame on this connection. \
+    /// Frames that exceed it are neither sent nor received.
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.capabilities.max_frame_size = size;
+    }
+
+    /// Send an arbitrary request and receive the daemon's response. \
+    /// This fails early, if the daemon doesn't support the message.
+    pub fn send_request(&mut self, message: Message) -> Result<Message, Error> {
+        self.capabilities.check_message(&message)?;
+        send_message_with(message, &self.capabilities, &mut self.stream)?;
+
+        self.receive_response()
+    }
+
+    /// Receive the next response. Failures are converted to [Error::Failure].
+    fn receive_response(&mut self) -> Result<Message, Error> {
+        receive_message_with(&self.capabilities, &mut self.stream)?.into_result()
+    }
+
+    /// Add a new task and return its id.
+    pub fn add(&mut self, message: AddMessage) -> Result<usize, Error> {
+        let response = self.send_request(add_request(message))?;
+
+        task_id(response)
+    }
+
+    /// Get the current state of the daemon.
+    pub fn status(&mut self) -> Result<State, Error> {
+        let response = self.send_request(Message::Status)?;
+
+        state(response)
+    }
+
+    /// Kill the selected tasks with the given signal or `SIGKILL` by default.
+    /// Returns the daemon's summary.
+    ///
+    /// If the tasks could only be partially killed, the summary lists the tasks that couldn't.
+    /// Use [Client::send_request] to get the individual results as [PartialSuccessMessage].
+    pub fn kill(&mut self, tasks: TaskSelection, signal: Option<Signal>) -> Result<String, Error> {
+        let response = self.send_request(kill_request(tasks, signal, None))?;
+
+        summary(response)
+    }
+
+    /// Kill the selected tasks with the escalation's signal and send its follow-up signal to
+    /// tasks that are still running after the grace period. Returns the daemon's summary.
+    pub fn kill_with_escalation(
+        &mut self,
+        tasks: TaskSelection,
+        escalation: KillEscalation,
+    ) -> Result<String, Error> {
+        let response = self.send_request(kill_request(tasks, None, Some(escalation)))?;
+
+        summary(response)
+    }
+
+    /// Change some properties of a task. Either all changes are applied or none of them.
+    /// Returns the daemon's summary.
+    pub fn edit(&mut self, task_id: usize, changes: Vec<TaskChange>) -> Result<String, Error> {
+        let message = Message::PartialEdit(PartialEditMessage { task_id, changes });
+        let response = self.send_request(message)?;
+
+        success(response)
+    }
+
+    /// Set an environment variable on the selected tasks. Returns the daemon's summary.
+    ///
+    /// Running tasks aren't changed. The summary lists them, if there are any.
+    pub fn set_env(
+        &mut self,
+        tasks: TaskSelection,
+        key: &str,
+        value: &str,
+    ) -> Result<String, Error> {
+        let message = Message::Env(EnvMessage::Set {
+            tasks,
+            key: key.into(),
+            value: value.into(),
+        });
+
+        self.send_env_request(message)
+    }
+
+    /// Remove an environment variable from the selected tasks. Returns the daemon's summary.
+    ///
+    /// Running tasks aren't changed. The summary lists them, if there are any.
+    pub fn unset_env(&mut self, tasks: TaskSelection, key: &str) -> Result<String, Error> {
+        let message = Message::Env(EnvMessage::Unset {
+            tasks,
+            key: key.into(),
+        });
+
+        self.send_env_request(message)
+    }
+
+    /// Send a request, which changes environment variables, and return the summary.
+    fn send_env_request(&mut self, message: Message) -> Result<String, Error> {
+        let response = self.send_request(message)?;
+
+        summary(response)
+    }
+
+    /// Get the environment variables of the selected tasks.
+    pub fn envs(
+        &mut self,
+        tasks: TaskSelection,
+    ) -> Result<BTreeMap<usize, HashMap<String, String>>, Error> {
+        let response = self.send_request(Message::Env(EnvMessage::List(tasks)))?;
+
+        envs(response)
+    }
+
+    /// Get the given tasks and their [snap] compressed output.
+    /// If no task ids are given, all tasks are returned. \
+    /// `lines` limits the output to the last lines of each task.
+    pub fn logs(
+        &mut self,
+        task_ids: Vec<usize>,
+        lines: Option<usize>,
+    ) -> Result<BTreeMap<usize, TaskLogMessage>, Error> {
+        let message = logs_request(task_ids, lines);
+        self.capabilities.check_message(&message)?;
+        send_message_with(message, &self.capabilities, &mut self.stream)?;
+
+        receive_log_response(&self.capabilities, &mut self.stream)
+    }
+
+    /// Follow the output of a task. If no task id is given, the daemon picks one.
+    /// `err` selects stderr instead of stdout.
+    ///
+    /// The daemon keeps sending output until the task finished.
+    /// The connection can't be used for other requests until the [LogStream] ended.
+    pub fn stream_log(
+        &mut self,
+        task_id: Option<usize>,
+        err: bool,
+    ) -> Result<LogStream<'_>, Error> {
+        let message = Message::StreamRequest(StreamRequestMessage { task_id, err });
+        self.capabilities.check_message(&message)?;
+        send_message_with(message, &self.capabilities, &mut self.stream)?;
+
+        Ok(LogStream {
+            client: self,
+            state: StreamState::default(),
+        })
+    }
+
+    /// Follow the raw output of several tasks at once.
+    /// Pass the request of a previous [OutputStream] to resume it after a reconnect.
+    ///
+    /// The daemon keeps sending output until all followed tasks finished.
+    /// The connection can't be used for other requests until the [OutputStream] ended.
+    pub fn stream_outputs(
+        &mut self,
+        request: MultiStreamRequestMessage,
+    ) -> Result<OutputStream<'_>, Error> {
+        let message = Message::MultiStreamRequest(request.clone());
+        self.capabilities.check_message(&message)?;
+        send_message_with(message, &self.capabilities, &mut self.stream)?;
+
+        Ok(OutputStream {
+            client: self,
+            state: StreamState::default(),
+            request,
+        })
+    }
+
+    /// Wait for the selected tasks to reach the condition. \
+    /// `timeout` is the maximum number of seconds the daemon waits.
+    ///
+    /// The daemon sends an update for each task, once it reached the condition or never can.
+    /// The connection can't be used for other requests until the [WaitStream] ended.
+    pub fn wait(
+        &mut self,
+        tasks: TaskSelection,
+        condition: WaitCondition,
+        timeout: Option<u64>,
+    ) -> Result<WaitStream<'_>, Error> {
+        let message = Message::Wait(WaitMessage {
+            tasks,
+            condition,
+            timeout,
+        });
+        self.capabilities.check_message(&message)?;
+        send_message_with(message, &self.capabilities, &mut self.stream)?;
+
+        Ok(WaitStream {
+            client: self,
+            state: StreamState::default(),
+            failures: Vec::new(),
+        })
+    }
+
+    /// Subscribe to all events that match the filters of the subscription. \
+    /// This returns, once the daemon confirmed the subscription, so no later events are missed.
+    ///
+    /// The daemon keeps sending events until it shuts down.
+    /// The connection can't be used for other requests while it's subscribed.
+    pub fn subscribe(&mut self, subscription: SubscribeMessage) -> Result<EventStream<'_>, Error> {
+        let response = self.send_request(Message::Subscribe(subscription))?;
+        success(response)?;
+
+        Ok(EventStream {
+            client: self,
+            state: StreamState::default(),
+        })
+    }
+}
+
+/// The events of a subscription, which are pushed by the daemon.
+/// The iterator ends, once the daemon shut down.
+pub struct EventStream<'a> {
+    client: &'a mut Client,
+    state: StreamState,
+}
+
+impl Iterator for EventStream<'_> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state.finished() {
+            return None;
+        }
+
+        let response = self.client.receive_response();
+        self.state.event(response).transpose()
+    }
+}
+
+/// The output of a task, which is streamed by the daemon.
+/// Each item is the next chunk of output. The iterator ends, once the task finished.
+pub struct LogStream<'a> {
+    client: &'a mut Client,
+    state: StreamState,
+}
+
+impl Iterator for LogStream<'_> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state.finished() {
+            return None;
+        }
+
+        let response = self.client.receive_response();
+        self.state.log(response).transpose()
+    }
+}
+
+/// The raw output of several tasks, which is streamed by the daemon.
+/// Each item is the next chunk of output. The iterator ends, once all followed tasks finished.
+pub struct OutputStream<'a> {
+    client: &'a mut Client,
+    state: StreamState,
+    request: MultiStreamRequestMessage,
+}
+
+impl OutputStream<'_> {
+    /// The request, which resumes this stream right after the last received chunk.
+    pub fn request(&self) -> &MultiStreamRequestMessage {
+        &self.request
+    }
+}
+
+impl Iterator for OutputStream<'_> {
+    type Item = Result<StreamChunkMessage, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state.finished() {
+            return None;
+        }
+
+        let response = self.client.receive_response();
+        self.state.output(&mut self.request, response).transpose()
+    }
+}
+
+/// The updates of tasks, which are waited for.
+/// Each item is the update of the next task that reached the condition or never can.
+/// The iterator ends, once all tasks have been handled or the wait timed out.
+pub struct WaitStream<'a> {
+    client: &'a mut Client,
+    state: StreamState,
+    failures: Vec<FailureMessage>,
+}
+
+impl WaitStream<'_> {
+    /// The tasks that didn't reach the condition, grouped by the reason.
+    /// This is only complete, once the iterator ended.
+    pub fn failures(&self) -> &[FailureMessage] {
+        &self.failures
+    }
+}
+
+impl Iterator for WaitStream<'_> {
+    type Item = Result<WaitUpdateMessage, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state.finished() {
+            return None;
+        }
+
+        let response = self.client.receive_response();
+        self.state.wait(&mut self.failures, response).transpose()
+    }
+}
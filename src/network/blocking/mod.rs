@@ -0,0 +1,5 @@
+/// A blocking version of the high-level async `Client`.
+pub mod client;
+/// Blocking versions of the functions in [crate::network::protocol], which send and receive
+/// messages over std streams.
+pub mod protocol;
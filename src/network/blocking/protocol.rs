@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::net::UnixStream;
+#[cfg(not(target_os = "windows"))]
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rustls::{ClientConnection, ServerName, StreamOwned};
+
+use crate::error::Error;
+use crate::network::compression::decompress;
+use crate::network::message::*;
+use crate::network::protocol::{
+    decode_header, decode_message, encode_frame, encode_message, finish_client_handshake,
+    local_handshake, serialize, Capabilities, Compression, LogCollector, WireFormat,
+    DEFAULT_MAX_FRAME_SIZE, INITIAL_PAYLOAD_CAPACITY,
+};
+use crate::network::tls::get_client_tls_config;
+use crate::settings::Shared;
+
+/// A blocking version of the async `Stream` trait. \
+/// It represents Unix- and Tls encrypted TcpStreams.
+pub trait Stream: Read + Write + Send {}
+#[cfg(not(target_os = "windows"))]
+impl Stream for UnixStream {}
+impl Stream for StreamOwned<ClientConnection, TcpStream> {}
+
+/// Convenience type, so we don't have type write `Box<dyn Stream>` all the time.
+pub type GenericStream = Box<dyn Stream>;
+
+/// Get a new stream for the client. \
+/// This can either be a UnixStream or a Tls encrypted TCPStream, depending on the parameters.
+pub fn get_client_stream(settings: &Shared) -> Result<GenericStream, Error> {
+    // Create a unix socket, if the config says so.
+    #[cfg(not(target_os = "windows"))]
+    if settings.use_unix_socket {
+        if !PathBuf::from(&settings.unix_socket_path()).exists() {
+            return Err(Error::FileNotFound(format!(
+                "Unix socket at path {:?}. Is the daemon started?",
+                &settings.unix_socket_path
+            )));
+        }
+        let stream = UnixStream::connect(settings.unix_socket_path())?;
+        return Ok(Box::new(stream));
+    }
+
+    // Connect to the daemon via TCP
+    let address = format!("{}:{}", &settings.host, &settings.port);
+    let tcp_stream = TcpStream::connect(&address).map_err(|_| {
+        Error::Connection(format!(
+            "Failed to connect to the daemon on {}. Did you start it?",
+            &address
+        ))
+    })?;
+
+    // Initialize the TLS layer. The actual TLS handshake happens on the first read or write.
+    let config = get_client_tls_config(settings)
+        .map_err(|err| Error::Connection(format!("Failed to initialize tls config {}.", err)))?;
+    let connection = ClientConnection::new(
+        Arc::new(config),
+        ServerName::try_from("pueue.local").unwrap(),
+    )
+    .map_err(|err| Error::Connection(format!("Failed to initialize tls {}.", err)))?;
+
+    Ok(Box::new(StreamOwned::new(connection, tcp_stream)))
+}
+
+/// The client side of the handshake. \
+/// The client sends its handshake first and then waits for the daemon's handshake.
+pub fn client_handshake(stream: &mut GenericStream) -> Result<Capabilities, Error> {
+    client_handshake_with_format(WireFormat::Cbor, stream)
+}
+
+/// Same as [client_handshake], but all messages on this connection use the given format.
+pub fn client_handshake_with_format(
+    format: WireFormat,
+    stream: &mut GenericStream,
+) -> Result<Capabilities, Error> {
+    let local = local_handshake();
+    send_bytes(&serialize(&local, format)?, stream)?;
+    let answer = receive_bytes(stream);
+
+    finish_client_handshake(&local, answer, format)
+}
+
+/// Convenience wrapper around send_bytes.
+/// Serialize a message and feed the bytes into send_bytes.
+pub fn send_message(message: Message, stream: &mut GenericStream) -> Result<(), Error> {
+    let payload = encode_message(&message, WireFormat::Cbor)?;
+
+    send_bytes(&payload, stream)
+}
+
+/// Same as [send_message], but the message is encoded in the format and compressed with the
+/// compression that have been negotiated during the handshake.
+pub fn send_message_with(
+    message: Message,
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+) -> Result<(), Error> {
+    let payload = encode_message(&message, capabilities.format)?;
+
+    write_frame(
+        &payload,
+        capabilities.compression,
+        capabilities.max_frame_size,
+        stream,
+    )
+}
+
+/// Send a Vec of bytes. Before the actual bytes are send, the size of the message
+/// is transmitted in an header of fixed size (u64).
+pub fn send_bytes(payload: &[u8], stream: &mut GenericStream) -> Result<(), Error> {
+    send_bytes_compressed(payload, Compression::None, stream)
+}
+
+/// Same as [send_bytes], but the payload is compressed with the given compression. \
+/// Small payloads or payloads that don't get any smaller are sent uncompressed.
+pub fn send_bytes_compressed(
+    payload: &[u8],
+    compression: Compression,
+    stream: &mut GenericStream,
+) -> Result<(), Error> {
+    write_frame(payload, compression, DEFAULT_MAX_FRAME_SIZE, stream)
+}
+
+fn write_frame(
+    payload: &[u8],
+    compression: Compression,
+    max_frame_size: usize,
+    stream: &mut GenericStream,
+) -> Result<(), Error> {
+    let (header, payload) = encode_frame(payload, compression, max_frame_size)?;
+
+    stream.write_all(&header)?;
+    stream.write_all(&payload)?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+/// Receive a byte stream. \
+/// This is part of the basic protocol beneath all communication. \
+/// Compressed payloads are decompressed transparently.
+pub fn receive_bytes(stream: &mut GenericStream) -> Result<Vec<u8>, Error> {
+    read_frame(stream, DEFAULT_MAX_FRAME_SIZE)
+}
+
+fn read_frame(stream: &mut GenericStream, max_frame_size: usize) -> Result<Vec<u8>, Error> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header)?;
+    let (message_size, compression) = decode_header(header, max_frame_size)?;
+
+    // Never read more than the announced payload.
+    // The buffer grows while the payload arrives.
+    let mut payload_bytes = Vec::with_capacity(message_size.min(INITIAL_PAYLOAD_CAPACITY));
+    stream
+        .take(message_size as u64)
+        .read_to_end(&mut payload_bytes)?;
+    if payload_bytes.len() < message_size {
+        return Err(Error::Connection(
+            "Connection went away while receiving payload.".into(),
+        ));
+    }
+
+    decompress(payload_bytes, compression, max_frame_size)
+}
+
+/// Convenience wrapper that receives a message and converts it into a Message.
+pub fn receive_message(stream: &mut GenericStream) -> Result<Message, Error> {
+    let payload_bytes = receive_bytes(stream)?;
+
+    decode_message(&payload_bytes, WireFormat::Cbor)
+}
+
+/// Same as [receive_message], but the message is decoded from the format that has been
+/// negotiated during the handshake.
+pub fn receive_message_with(
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+) -> Result<Message, Error> {
+    let payload_bytes = read_frame(stream, capabilities.max_frame_size)?;
+
+    decode_message(&payload_bytes, capabilities.format)
+}
+
+/// Receive the logs of tasks. \
+/// This handles both, a single [Message::LogResponse] and a sequence of [Message::LogChunk]s.
+/// Any other message is returned as an error.
+pub fn receive_log_response(
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+) -> Result<BTreeMap<usize, TaskLogMessage>, Error> {
+    let mut collector = LogCollector::default();
+    loop {
+        let message = receive_message_with(capabilities, stream)?;
+        if let Some(logs) = collector.add(message)? {
+            return Ok(logs);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+#[cfg(any(feature = "async", feature = "blocking"))]
+use std::io::Read;
+
+#[cfg(any(feature = "async", feature = "blocking"))]
+use crate::error::Error;
+
+/// Payloads smaller than this aren't worth to be compressed.
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub const MIN_COMPRESSION_SIZE: usize = 1024;
+
+/// The compression that's used for a single message frame.
+///
+/// The compression of each frame is flagged in the highest byte of its header.
+/// Uncompressed frames have no flag, which keeps them compatible with older versions. \
+/// Which compression may be used on a connection is negotiated during the handshake.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Snap,
+    Zstd,
+}
+
+#[cfg(any(feature = "async", feature = "blocking"))]
+impl Compression {
+    /// The flag of this compression in the frame header.
+    pub(crate) fn flag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Snap => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_flag(flag: u8) -> Result<Compression, Error> {
+        match flag {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Snap),
+            2 => Ok(Compression::Zstd),
+            _ => Err(Error::MessageDeserialization(format!(
+                "Unknown frame compression flag {}",
+                flag
+            ))),
+        }
+    }
+}
+
+/// Compress a payload. \
+/// Returns `None`, if the payload shouldn't be compressed, because it's either too small or the
+/// compression doesn't make it any smaller.
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub(crate) fn compress(payload: &[u8], compression: Compression) -> Result<Option<Vec<u8>>, Error> {
+    if payload.len() < MIN_COMPRESSION_SIZE {
+        return Ok(None);
+    }
+
+    let compressed = match compression {
+        Compression::None => return Ok(None),
+        Compression::Snap => snap::raw::Encoder::new()
+            .compress_vec(payload)
+            .map_err(|err| Error::MessageSerialization(err.to_string()))?,
+        Compression::Zstd => zstd::bulk::compress(payload, 0)?,
+    };
+
+    if compressed.len() >= payload.len() {
+        return Ok(None);
+    }
+
+    Ok(Some(compressed))
+}
+
+/// Decompress a payload. \
+/// The decompressed payload may be at most `max_size` bytes large, which protects us from
+/// decompression bombs.
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub(crate) fn decompress(
+    payload: Vec<u8>,
+    compression: Compression,
+    max_size: usize,
+) -> Result<Vec<u8>, Error> {
+    let decompressed = match compression {
+        Compression::None => return Ok(payload),
+        Compression::Snap => {
+            // Snap stores the decompressed size, so we can check it before decompressing.
+            let size = snap::raw::decompress_len(&payload)
+                .map_err(|err| Error::MessageDeserialization(err.to_string()))?;
+            if size > max_size {
+                return Err(Error::FrameTooLarge {
+                    size,
+                    max: max_size,
+                });
+            }
+
+            snap::raw::Decoder::new()
+                .decompress_vec(&payload)
+                .map_err(|err| Error::MessageDeserialization(err.to_string()))?
+        }
+        Compression::Zstd => {
+            // Read at most one byte more than allowed, to detect oversized payloads.
+            let mut decompressed = Vec::new();
+            zstd::stream::read::Decoder::new(payload.as_slice())?
+                .take(max_size as u64 + 1)
+                .read_to_end(&mut decompressed)?;
+
+            decompressed
+        }
+    };
+
+    if decompressed.len() > max_size {
+        return Err(Error::FrameTooLarge {
+            size: decompressed.len(),
+            max: max_size,
+        });
+    }
+
+    Ok(decompressed)
+}
+
+#[cfg(all(test, any(feature = "async", feature = "blocking")))]
+mod test {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_compression_roundtrip() {
+        let payload = "pueue ".repeat(1000).into_bytes();
+
+        for compression in [Compression::Snap, Compression::Zstd] {
+            let compressed = compress(&payload, compression).unwrap().unwrap();
+            assert!(compressed.len() < payload.len());
+
+            let decompressed = decompress(compressed.clone(), compression, payload.len()).unwrap();
+            assert_eq!(decompressed, payload);
+
+            // Payloads that exceed the maximum size are rejected.
+            let result = decompress(compressed, compression, payload.len() - 1);
+            assert!(matches!(result, Err(Error::FrameTooLarge { .. })));
+        }
+
+        // Small payloads aren't compressed at all.
+        assert_eq!(compress(b"pueue", Compression::Zstd).unwrap(), None);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::debug;
+use tokio::io::{split, WriteHalf};
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+use tokio::task::JoinHandle;
+
+use crate::error::Error;
+use crate::network::message::{Envelope, Message};
+use crate::network::protocol::{
+    read_envelope, write_envelope, Capabilities, GenericStream, FEATURE_MULTIPLEXING,
+};
+
+/// All requests that are waiting for responses, by their request id. \
+/// This is `None`, as soon as the connection went away.
+type PendingRequests = Arc<Mutex<Option<HashMap<u64, mpsc::UnboundedSender<Message>>>>>;
+
+/// The client side of a multiplexed connection.
+///
+/// The dispatcher wraps every request in an [Envelope] with a unique request id and routes all
+/// responses to the request with the same id. \
+/// This allows to have multiple requests in flight on a single connection, e.g. a log stream
+/// alongside some status polls.
+///
+/// The handshake has to be done before the stream is handed to the dispatcher and both sides
+/// have to support the [multiplexing feature](crate::network::protocol::FEATURE_MULTIPLEXING).
+pub struct Dispatcher {
+    writer: AsyncMutex<WriteHalf<GenericStream>>,
+    capabilities: Capabilities,
+    pending: PendingRequests,
+    next_request_id: AtomicU64,
+    reader: JoinHandle<()>,
+}
+
+impl Dispatcher {
+    /// Create a new dispatcher on top of a connection. \
+    /// This spawns a tokio task, which receives all incoming messages and routes them to their
+    /// requests.
+    ///
+    /// All messages are encoded and compressed as negotiated during the handshake.
+    /// This fails with [Error::UnsupportedFeature], if multiplexing hasn't been negotiated.
+    pub fn new(stream: GenericStream, capabilities: &Capabilities) -> Result<Dispatcher, Error> {
+        if !capabilities.supports(FEATURE_MULTIPLEXING) {
+            return Err(Error::UnsupportedFeature(FEATURE_MULTIPLEXING.to_string()));
+        }
+
+        let (mut read_half, writer) = split(stream);
+        let pending: PendingRequests = Arc::new(Mutex::new(Some(HashMap::new())));
+
+        let reader_pending = pending.clone();
+        let reader_capabilities = capabilities.clone();
+        let reader = tokio::spawn(async move {
+            loop {
+                let envelope = match read_envelope(&reader_capabilities, &mut read_half).await {
+                    Ok(envelope) => envelope,
+                    Err(err) => {
+                        debug!("Stopped receiving on multiplexed connection: {}", err);
+                        break;
+                    }
+                };
+
+                let pending = reader_pending.lock().unwrap();
+                match pending
+                    .as_ref()
+                    .and_then(|pending| pending.get(&envelope.request_id))
+                {
+                    // Responses to requests whose receiver has been dropped are simply discarded.
+                    Some(sender) => {
+                        let _ = sender.send(envelope.message);
+                    }
+                    None => debug!("Got response for unknown request {}", envelope.request_id),
+                }
+            }
+
+            // Drop all senders, which notifies all waiting requests that the connection is gone.
+            reader_pending.lock().unwrap().take();
+        });
+
+        Ok(Dispatcher {
+            writer: AsyncMutex::new(writer),
+            capabilities: capabilities.clone(),
+            pending,
+            next_request_id: AtomicU64::new(0),
+            reader,
+        })
+    }
+
+    /// Send a request and wait for its first response.
+    pub async fn request(&self, message: Message) -> Result<Message, Error> {
+        let mut responses = self.request_stream(message).await?;
+        responses.next().await
+    }
+
+    /// Send a request, which might be answered with several messages, such as a
+    /// [Message::StreamRequest]. \
+    /// The returned [Responses] receive all messages the daemon sends for this request.
+    ///
+    /// Messages the daemon doesn't support are rejected with [Error::UnsupportedFeature].
+    pub async fn request_stream(&self, message: Message) -> Result<Responses, Error> {
+        self.capabilities.check_message(&message)?;
+
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(request_id, sender),
+            None => return Err(connection_closed()),
+        };
+
+        // Create the handle before sending, so the request is cleaned up on errors.
+        let responses = Responses {
+            request_id,
+            receiver,
+            pending: self.pending.clone(),
+        };
+
+        let envelope = Envelope {
+            request_id,
+            message,
+        };
+        let mut writer = self.writer.lock().await;
+        write_envelope(&envelope, &self.capabilities, &mut *writer).await?;
+
+        Ok(responses)
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// All responses to a single request. \
+/// Once this is dropped, any further responses to the request are discarded.
+pub struct Responses {
+    request_id: u64,
+    receiver: mpsc::UnboundedReceiver<Message>,
+    pending: PendingRequests,
+}
+
+impl Responses {
+    /// The id of the request these responses belong to.
+    pub fn request_id(&self) -> u64 {
+        self.request_id
+    }
+
+    /// Wait for the next response. \
+    /// This fails, if the connection went away.
+    pub async fn next(&mut self) -> Result<Message, Error> {
+        self.receiver.recv().await.ok_or_else(connection_closed)
+    }
+}
+
+impl Drop for Responses {
+    fn drop(&mut self) {
+        if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+            pending.remove(&self.request_id);
+        }
+    }
+}
+
+fn connection_closed() -> Error {
+    Error::Connection("Connection went away while waiting for a response.".into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::task;
+
+    use crate::network::message::{create_success_message, MoveToGroupMessage};
+    use crate::network::protocol::{
+        local_handshake, negotiate, receive_envelope, send_envelope, GenericListener, WireFormat,
+        FEATURE_MOVE_TO_GROUP,
+    };
+
+    #[tokio::test]
+    /// Responses are routed to the correct request, even if they arrive in a different order.
+    async fn test_dispatcher_routes_responses() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let listener: GenericListener = Box::new(listener);
+
+        let mut capabilities = negotiate(&local_handshake(), &local_handshake())?;
+        capabilities.format = WireFormat::Json;
+
+        // Receive two requests and answer them in reverse order.
+        let daemon_capabilities = capabilities.clone();
+        task::spawn(async move {
+            let mut stream = listener.accept().await.unwrap();
+            let first = receive_envelope(&daemon_capabilities, &mut stream)
+                .await
+                .unwrap();
+            let second = receive_envelope(&daemon_capabilities, &mut stream)
+                .await
+                .unwrap();
+            for envelope in [second, first] {
+                send_envelope(
+                    envelope.request_id,
+                    envelope.message,
+                    &daemon_capabilities,
+                    &mut stream,
+                )
+                .await
+                .unwrap();
+            }
+        });
+
+        let client: GenericStream = Box::new(TcpStream::connect(&addr).await?);
+        let dispatcher = Dispatcher::new(client, &capabilities)?;
+
+        let mut first = dispatcher
+            .request_stream(create_success_message("first"))
+            .await?;
+        let mut second = dispatcher
+            .request_stream(create_success_message("second"))
+            .await?;
+
+        assert_eq!(second.next().await?, create_success_message("second"));
+        assert_eq!(first.next().await?, create_success_message("first"));
+
+        // The daemon closed the connection.
+        assert!(dispatcher.request(Message::Status).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    /// Connections without multiplexing and unsupported messages are rejected.
+    async fn test_dispatcher_checks_features() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let capabilities = negotiate(&local_handshake(), &local_handshake())?;
+
+        let mut without_multiplexing = capabilities.clone();
+        without_multiplexing
+            .features
+            .retain(|feature| feature != FEATURE_MULTIPLEXING);
+        let client: GenericStream = Box::new(TcpStream::connect(&addr).await?);
+        assert!(matches!(
+            Dispatcher::new(client, &without_multiplexing),
+            Err(Error::UnsupportedFeature(feature)) if feature == FEATURE_MULTIPLEXING
+        ));
+
+        let mut without_move = capabilities;
+        without_move
+            .features
+            .retain(|feature| feature != FEATURE_MOVE_TO_GROUP);
+        let client: GenericStream = Box::new(TcpStream::connect(&addr).await?);
+        let dispatcher = Dispatcher::new(client, &without_move)?;
+        let message = Message::MoveToGroup(MoveToGroupMessage {
+            task_ids: vec![0],
+            group: "default".into(),
+        });
+        assert!(matches!(
+            dispatcher.request_stream(message).await,
+            Err(Error::UnsupportedFeature(feature)) if feature == FEATURE_MOVE_TO_GROUP
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::prelude::*;
+use serde::{de, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use snap::read::FrameDecoder;
+use subtle::ConstantTimeEq;
+
+use crate::error::Error;
+use crate::network::client::Client;
+use crate::network::message::*;
+use crate::network::secret::read_shared_secret;
+use crate::settings::Shared;
+use crate::state::PUEUE_DEFAULT_GROUP;
+use crate::task::Task;
+
+/// Everything the request handlers need to talk to the daemon.
+struct Gateway {
+    settings: Shared,
+    secret: Vec<u8>,
+}
+
+/// Serve the REST API on the given port of localhost. \
+/// The gateway only listens on localhost, as it's meant to be used by local frontends.
+///
+/// See [router] for the available endpoints.
+pub async fn serve(port: u16, settings: Shared) -> Result<(), Error> {
+    let router = router(settings)?;
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let listener = tokio::net::TcpListener::bind(address).await?;
+
+    axum::serve(listener, router).await?;
+
+    Ok(())
+}
+
+/// Create the router of the REST API. \
+/// Each request is translated to a [Message], which is sent to the daemon by a new [Client].
+///
+/// - `GET /tasks` returns all tasks by their id.
+/// - `POST /tasks` adds a new task. The body is an [AddTaskRequest].
+///   Returns the id of the new task as `{"task_id": 0}`.
+/// - `POST /tasks/:id/kill` kills a task. The optional `signal`, `children` and `process_group`
+///   query parameters correspond to the fields of the [KillMessage]. Signals are given by their
+///   name or number.
+///   If a `grace_period` is given, the `signal` (`SigTerm` by default) is escalated to the
+///   optional `follow_up` signal (`SigKill` by default) via a [KillEscalation].
+/// - `GET /tasks/:id/log` returns the task and its output. The optional `lines` query parameter
+///   limits the output to its last lines.
+///
+/// All requests have to be authenticated with the daemon's shared secret as bearer token, i.e.
+/// `Authorization: Bearer <secret>`. \
+/// Errors are returned as `{"error": "..."}`. \
+/// Requests that are rejected by the daemon result in a `400` or a `404` for missing tasks and
+/// groups. Their body additionally contains the `code` and `task_ids` of the [FailureMessage].
+/// Failed connections to the daemon result in a `502`.
+pub fn router(settings: Shared) -> Result<Router, Error> {
+    let secret = read_shared_secret(&settings.shared_secret_path())?;
+    let gateway = Arc::new(Gateway { settings, secret });
+
+    let router = Router::new()
+        .route("/tasks", get(get_tasks).post(add_task))
+        .route("/tasks/:id/kill", post(kill_task))
+        .route("/tasks/:id/log", get(get_log))
+        .layer(middleware::from_fn_with_state(
+            gateway.clone(),
+            authenticate,
+        ))
+        .with_state(gateway);
+
+    Ok(router)
+}
+
+/// The body of a `POST /tasks` request. \
+/// Only `command` and `path` are required.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct AddTaskRequest {
+    pub command: String,
+    pub path: String,
+    #[serde(default)]
+    pub envs: BTreeMap<String, String>,
+    #[serde(default)]
+    pub start_immediately: bool,
+    #[serde(default)]
+    pub stashed: bool,
+    #[serde(default = "default_group")]
+    pub group: String,
+    #[serde(default)]
+    pub enqueue_at: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub dependencies: Vec<usize>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub kill_escalation: Option<KillEscalation>,
+}
+
+fn default_group() -> String {
+    PUEUE_DEFAULT_GROUP.to_string()
+}
+
+/// The response of a `GET /tasks/:id/log` request. \
+/// The output is decompressed and lossily converted to UTF-8.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct TaskLogResponse {
+    pub task: Task,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+}
+
+/// Signals are parsed from their name or number, e.g. `?signal=sigterm` or `?signal=15`.
+#[derive(Deserialize)]
+struct KillQuery {
+    #[serde(default, deserialize_with = "deserialize_signal")]
+    signal: Option<Signal>,
+    #[serde(default)]
+    children: bool,
+    #[serde(default)]
+    process_group: bool,
+    #[serde(default)]
+    grace_period: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_signal")]
+    follow_up: Option<Signal>,
+}
+
+fn deserialize_signal<'de, D>(deserializer: D) -> Result<Option<Signal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let signal: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+    signal
+        .map(|signal| {
+            Signal::from_str(&signal)
+                .map_err(|_| de::Error::custom(format!("Unknown signal \"{}\"", signal)))
+        })
+        .transpose()
+}
+
+#[derive(Deserialize)]
+struct LogQuery {
+    #[serde(default)]
+    lines: Option<usize>,
+}
+
+/// Errors of the gateway, which are sent to the HTTP client.
+enum GatewayError {
+    Unauthorized,
+    NotFound(String),
+    /// The daemon rejected the request.
+    Failure(FailureMessage),
+    /// Anything that went wrong while talking to the daemon.
+    Daemon(Error),
+}
+
+impl From<Error> for GatewayError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Failure(failure) => GatewayError::Failure(failure),
+            err => GatewayError::Daemon(err),
+        }
+    }
+}
+
+impl IntoResponse for GatewayError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            GatewayError::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                json!({ "error": "Invalid or missing secret" }),
+            ),
+            GatewayError::NotFound(text) => (StatusCode::NOT_FOUND, json!({ "error": text })),
+            GatewayError::Failure(failure) => {
+                let status = match failure.code {
+                    ErrorCode::TaskNotFound | ErrorCode::GroupNotFound => StatusCode::NOT_FOUND,
+                    _ => StatusCode::BAD_REQUEST,
+                };
+                let body = json!({
+                    "error": failure.text,
+                    "code": failure.code,
+                    "task_ids": failure.task_ids,
+                });
+                (status, body)
+            }
+            GatewayError::Daemon(err) => {
+                (StatusCode::BAD_GATEWAY, json!({ "error": err.to_string() }))
+            }
+        };
+
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Check the bearer token of a request against the shared secret.
+async fn authenticate(
+    State(gateway): State<Arc<Gateway>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, GatewayError> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match token {
+        // Compare in constant time, so the secret can't be guessed from the response times.
+        Some(token) if bool::from(token.as_bytes().ct_eq(gateway.secret.as_slice())) => {
+            Ok(next.run(request).await)
+        }
+        _ => Err(GatewayError::Unauthorized),
+    }
+}
+
+async fn get_tasks(
+    State(gateway): State<Arc<Gateway>>,
+) -> Result<Json<BTreeMap<usize, Task>>, GatewayError> {
+    let mut client = Client::connect(&gateway.settings).await?;
+    let state = client.status().await?;
+
+    Ok(Json(state.tasks))
+}
+
+async fn add_task(
+    State(gateway): State<Arc<Gateway>>,
+    Json(body): Json<AddTaskRequest>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let message = AddMessage {
+        command: body.command,
+        path: body.path,
+        envs: body.envs.into_iter().collect(),
+        start_immediately: body.start_immediately,
+        stashed: body.stashed,
+        group: body.group,
+        enqueue_at: body.enqueue_at,
+        dependencies: body.dependencies,
+        label: body.label,
+        print_task_id: true,
+        batch_dependencies: Vec::new(),
+        kill_escalation: body.kill_escalation,
+    };
+
+    let mut client = Client::connect(&gateway.settings).await?;
+    let task_id = client.add(message).await?;
+
+    Ok((StatusCode::CREATED, Json(json!({ "task_id": task_id }))))
+}
+
+async fn kill_task(
+    State(gateway): State<Arc<Gateway>>,
+    Path(task_id): Path<usize>,
+    Query(query): Query<KillQuery>,
+) -> Result<Json<serde_json::Value>, GatewayError> {
+    let escalation = query.grace_period.map(|grace_period| {
+        let default = KillEscalation::default();
+        KillEscalation {
+            signal: query.signal.clone().unwrap_or(default.signal),
+            grace_period,
+            follow_up: query.follow_up.unwrap_or(default.follow_up),
+        }
+    });
+    let message = Message::Kill(KillMessage {
+        tasks: TaskSelection::TaskIds(vec![task_id]),
+        children: query.children,
+        signal: query.signal,
+        process_group: query.process_group,
+        escalation,
+    });
+
+    let mut client = Client::connect(&gateway.settings).await?;
+    let text = match client.send_request(message).await? {
+        Message::Success(text) => text,
+        Message::PartialSuccess(result) => result.text,
+        message => return Err(Error::UnexpectedResponse(format!("{:?}", message)).into()),
+    };
+
+    Ok(Json(json!({ "message": text })))
+}
+
+async fn get_log(
+    State(gateway): State<Arc<Gateway>>,
+    Path(task_id): Path<usize>,
+    Query(query): Query<LogQuery>,
+) -> Result<Json<TaskLogResponse>, GatewayError> {
+    let mut client = Client::connect(&gateway.settings).await?;
+    let mut logs = client.logs(vec![task_id], query.lines).await?;
+    let log = logs
+        .remove(&task_id)
+        .ok_or_else(|| GatewayError::NotFound(format!("Task {} doesn't exist", task_id)))?;
+
+    Ok(Json(TaskLogResponse {
+        task: log.task,
+        stdout: log
+            .stdout
+            .map(|output| decompress_output(&output))
+            .transpose()?,
+        stderr: log
+            .stderr
+            .map(|output| decompress_output(&output))
+            .transpose()?,
+    }))
+}
+
+/// Decompress the [snap] compressed output of a task.
+fn decompress_output(output: &[u8]) -> Result<String, Error> {
+    let mut decompressed = Vec::new();
+    FrameDecoder::new(Cursor::new(output)).read_to_end(&mut decompressed)?;
+
+    Ok(String::from_utf8_lossy(&decompressed).into_owned())
+}
@@ -4,8 +4,17 @@ use chrono::prelude::*;
 use serde_derive::{Deserialize, Serialize};
 use strum_macros::{Display, EnumString};
 
-use crate::state::{Group, State};
-use crate::task::Task;
+use crate::archive::ArchivedTask;
+use crate::error::Error;
+use crate::network::protocol::{Capabilities, FEATURE_TASK_EDIT};
+use crate::state::{Group, QueueOrdering, State, TimeWindow};
+use crate::task::{Task, TaskResult, TaskStatus};
+
+pub use crate::archive::ArchiveQueryMessage;
+pub use crate::error::{ErrorCode, FailureMessage};
+pub use crate::log::OutputKind;
+pub use crate::state::TaskSelection;
+pub use crate::task::{KillEscalation, Signal, TaskChange};
 
 /// This is the main message enum. \
 /// Everything that's communicated in Pueue can be serialized as this enum.
@@ -16,6 +25,8 @@ pub enum Message {
     Switch(SwitchMessage),
     Stash(Vec<usize>),
     Enqueue(EnqueueMessage),
+    /// Move tasks to another group, while keeping their ids and dependencies.
+    MoveToGroup(MoveToGroupMessage),
 
     Start(StartMessage),
     Restart(RestartMessage),
@@ -32,39 +43,74 @@ pub enum Message {
     EditResponse(EditResponseMessage),
     /// The client sends the edited details to the daemon.
     Edit(EditMessage),
+    /// Change some properties of a task in one go, without the three-step protocol.
+    PartialEdit(PartialEditMessage),
 
     Group(GroupMessage),
     GroupResponse(GroupResponseMessage),
 
+    /// Set, unset or list the environment variables of tasks.
+    Env(EnvMessage),
+    EnvResponse(EnvResponseMessage),
+
     Status,
     StatusResponse(Box<State>),
     Log(LogRequestMessage),
     LogResponse(BTreeMap<usize, TaskLogMessage>),
+    /// Large log responses are sent as a sequence of chunks instead of a single
+    /// [Message::LogResponse].
+    LogChunk(LogChunkMessage),
 
     /// The client requests a continuous stream of a task's log.
     StreamRequest(StreamRequestMessage),
     /// The next chunk of output, that's send to the client.
     Stream(String),
+    /// The client requests a continuous stream of the raw output of several tasks.
+    /// The daemon sends a [Message::StreamChunk] for each new piece of output and a
+    /// [Message::Success], once all followed tasks finished.
+    MultiStreamRequest(MultiStreamRequestMessage),
+    StreamChunk(StreamChunkMessage),
+
+    /// The client waits for tasks to reach a condition. The daemon keeps the connection open and
+    /// sends a [Message::WaitUpdate] for each task, once it reached the condition or never can.
+    /// The final response is a [Message::Success] or, if the condition hasn't been reached by
+    /// all tasks, a [Message::PartialSuccess] with [ErrorCode::ConditionUnreachable] and
+    /// [ErrorCode::Timeout] failures. Invalid requests are answered with a
+    /// [Message::StructuredFailure].
+    Wait(WaitMessage),
+    WaitUpdate(WaitUpdateMessage),
+
+    /// The client subscribes to events. The daemon keeps the connection open and pushes
+    /// an [Message::Event] for each matching event.
+    Subscribe(SubscribeMessage),
+    Event(Event),
 
     /// The boolean decides, whether the children should be get a SIGTERM as well.
     Reset(ResetMessage),
     Clean(CleanMessage),
+    /// Request tasks from the archive of cleaned tasks.
+    ArchiveQuery(ArchiveQueryMessage),
+    ArchiveResponse(Vec<ArchivedTask>),
+    /// Move tasks with the given archive ids back into the state.
+    ArchiveRestore(Vec<usize>),
     DaemonShutdown(Shutdown),
 
     Success(String),
     Failure(String),
+    /// A failure with a machine readable [ErrorCode].
+    /// Only sent to clients that support it, otherwise [Message::Failure] is used.
+    StructuredFailure(FailureMessage),
+    /// The result of a request that has only been applied to some of the affected tasks.
+    /// Only sent to clients that support it, otherwise [Message::Success] or
+    /// [Message::Failure] is used.
+    PartialSuccess(PartialSuccessMessage),
 
     Parallel(ParallelMessage),
-}
 
-/// This enum is used to express a selection of tasks.
-/// As commands can be executed on various sets of tasks, we need some kind of datastructure to
-/// explicitly and unambiguously specify the selection.
-#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
-pub enum TaskSelection {
-    TaskIds(Vec<usize>),
-    Group(String),
-    All,
+    /// Multiple requests that are handled in one go.
+    Batch(BatchMessage),
+    /// The responses to a [Message::Batch], in the same order as the batch's messages.
+    BatchResponse(Vec<Message>),
 }
 
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
@@ -79,6 +125,33 @@ pub struct AddMessage {
     pub dependencies: Vec<usize>,
     pub label: Option<String>,
     pub print_task_id: bool,
+    /// Only used inside a [BatchMessage]. \
+    /// Indices of earlier [Message::Add] items of the same batch, whose tasks this task depends on.
+    #[serde(default)]
+    pub batch_dependencies: Vec<usize>,
+    /// The default escalation to kill this task. See [Task::kill_escalation].
+    #[serde(default)]
+    pub kill_escalation: Option<KillEscalation>,
+}
+
+impl AddMessage {
+    /// Resolve the `batch_dependencies` to the ids of the tasks that have been created by the
+    /// respective batch items and add them to the normal `dependencies`.
+    ///
+    /// `created_task_ids` maps the index of each batch item to the id of the task it created.
+    pub fn resolve_batch_dependencies(
+        &mut self,
+        created_task_ids: &BTreeMap<usize, usize>,
+    ) -> Result<(), Error> {
+        for index in self.batch_dependencies.drain(..) {
+            let task_id = created_task_ids.get(&index).ok_or_else(|| {
+                invalid_batch(format!("Batch item {} didn't create a task.", index))
+            })?;
+            self.dependencies.push(*task_id);
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
@@ -93,6 +166,12 @@ pub struct EnqueueMessage {
     pub enqueue_at: Option<DateTime<Local>>,
 }
 
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct MoveToGroupMessage {
+    pub task_ids: Vec<usize>,
+    pub group: String,
+}
+
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
 pub struct StartMessage {
     pub tasks: TaskSelection,
@@ -118,25 +197,9 @@ pub struct PauseMessage {
     pub tasks: TaskSelection,
     pub wait: bool,
     pub children: bool,
-}
-
-/// This is a small custom Enum for all currently supported unix signals.
-/// Supporting all unix signals would be a mess, since there is a LOT of them.
-///
-/// This is also needed for usage in clap, since nix's Signal doesn't implement [Display] and
-/// [std::str::FromStr].
-#[derive(PartialEq, Clone, Debug, Deserialize, Serialize, Display, EnumString)]
-pub enum Signal {
-    #[strum(serialize = "SigInt", serialize = "sigint", serialize = "2")]
-    SigInt,
-    #[strum(serialize = "SigKill", serialize = "sigkill", serialize = "9")]
-    SigKill,
-    #[strum(serialize = "SigTerm", serialize = "sigterm", serialize = "15")]
-    SigTerm,
-    #[strum(serialize = "SigCont", serialize = "sigcont", serialize = "18")]
-    SigCont,
-    #[strum(serialize = "SigStop", serialize = "sigstop", serialize = "19")]
-    SigStop,
+    /// Automatically resume the paused group(s) at this point in time.
+    #[serde(default)]
+    pub resume_at: Option<DateTime<Local>>,
 }
 
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
@@ -144,6 +207,13 @@ pub struct KillMessage {
     pub tasks: TaskSelection,
     pub children: bool,
     pub signal: Option<Signal>,
+    /// Send the signal to the whole process group of each task instead of only its direct child.
+    #[serde(default)]
+    pub process_group: bool,
+    /// Send a follow-up signal to tasks that are still running after a grace period.
+    /// `signal` is ignored, if this is set.
+    #[serde(default)]
+    pub escalation: Option<KillEscalation>,
 }
 
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
@@ -152,18 +222,106 @@ pub struct SendMessage {
     pub input: String,
 }
 
+/// The edited details of a locked task.
+///
+/// Clients without the `task_edit` feature only send the `command` and `path`.
+/// Use [EditMessage::into_changes] to get the changes that should be applied.
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
 pub struct EditMessage {
     pub task_id: usize,
     pub command: String,
     pub path: String,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub envs: HashMap<String, String>,
+    #[serde(default)]
+    pub group: String,
+    #[serde(default)]
+    pub dependencies: Vec<usize>,
+    /// Only used for stashed tasks.
+    #[serde(default)]
+    pub enqueue_at: Option<DateTime<Local>>,
 }
 
+impl EditMessage {
+    /// Get the changes of this edit. \
+    /// Only the `command` and `path` are changed, if the client doesn't support the `task_edit`
+    /// feature. Otherwise, its task would lose all other properties.
+    /// An empty `group` keeps the task in its group.
+    ///
+    /// `status` is the status of the task before it has been locked. The `enqueue_at` of stashed
+    /// tasks is always applied, so it can be cleared as well.
+    pub fn into_changes(self, capabilities: &Capabilities, status: &TaskStatus) -> Vec<TaskChange> {
+        let mut changes = vec![
+            TaskChange::Command(self.command),
+            TaskChange::Path(self.path),
+        ];
+        if !capabilities.supports(FEATURE_TASK_EDIT) {
+            return changes;
+        }
+
+        changes.push(TaskChange::Label(self.label));
+        changes.push(TaskChange::Envs(self.envs));
+        // Clients that don't know about groups leave it empty.
+        if !self.group.is_empty() {
+            changes.push(TaskChange::Group(self.group));
+        }
+        changes.push(TaskChange::Dependencies(self.dependencies));
+        if self.enqueue_at.is_some() || matches!(status, TaskStatus::Stashed { .. }) {
+            changes.push(TaskChange::EnqueueAt(self.enqueue_at));
+        }
+
+        changes
+    }
+}
+
+/// The current details of a task, which has been locked for editing.
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
 pub struct EditResponseMessage {
     pub task_id: usize,
     pub command: String,
     pub path: String,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub envs: HashMap<String, String>,
+    #[serde(default)]
+    pub group: String,
+    #[serde(default)]
+    pub dependencies: Vec<usize>,
+    #[serde(default)]
+    pub enqueue_at: Option<DateTime<Local>>,
+}
+
+impl EditResponseMessage {
+    pub fn from_task(task: &Task) -> EditResponseMessage {
+        // Locked tasks keep their actual status in `prev_status`.
+        let enqueue_at = match (&task.status, &task.prev_status) {
+            (TaskStatus::Stashed { enqueue_at }, _)
+            | (TaskStatus::Locked, TaskStatus::Stashed { enqueue_at }) => *enqueue_at,
+            _ => None,
+        };
+
+        EditResponseMessage {
+            task_id: task.id,
+            command: task.original_command.clone(),
+            path: task.path.clone(),
+            label: task.label.clone(),
+            envs: task.envs.clone(),
+            group: task.group.clone(),
+            dependencies: task.dependencies.clone(),
+            enqueue_at,
+        }
+    }
+}
+
+/// Change some properties of a task, without locking it first. \
+/// Either all changes are applied or none of them. See [State::edit_task] for the validation.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct PartialEditMessage {
+    pub task_id: usize,
+    pub changes: Vec<TaskChange>,
 }
 
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
@@ -171,6 +329,29 @@ pub enum GroupMessage {
     Add {
         name: String,
         parallel_tasks: Option<usize>,
+        #[serde(default)]
+        time_windows: Option<Vec<TimeWindow>>,
+        #[serde(default)]
+        ordering: Option<QueueOrdering>,
+        #[serde(default)]
+        parent: Option<String>,
+    },
+    /// Change the configuration of an existing group.
+    /// Only fields that are set will be changed.
+    Edit {
+        name: String,
+        time_windows: Option<Vec<TimeWindow>>,
+        ordering: Option<QueueOrdering>,
+    },
+    /// Set or remove the parent group of a group.
+    SetParent {
+        name: String,
+        parent: Option<String>,
+    },
+    /// Rename a group. Its tasks and configuration are kept.
+    Rename {
+        name: String,
+        new_name: String,
     },
     Remove(String),
     List,
@@ -181,6 +362,27 @@ pub struct GroupResponseMessage {
     pub groups: BTreeMap<String, Group>,
 }
 
+/// Manipulate the environment variables of several tasks at once. \
+/// Running tasks cannot be changed, as their process already got its environment.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub enum EnvMessage {
+    /// Set a variable on all selected tasks.
+    Set {
+        tasks: TaskSelection,
+        key: String,
+        value: String,
+    },
+    /// Remove a variable from all selected tasks.
+    Unset { tasks: TaskSelection, key: String },
+    /// Get the variables of all selected tasks.
+    List(TaskSelection),
+}
+
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct EnvResponseMessage {
+    pub envs: BTreeMap<usize, HashMap<String, String>>,
+}
+
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
 pub struct ResetMessage {
     pub children: bool,
@@ -193,6 +395,14 @@ pub struct CleanMessage {
 
     #[serde(default = "Option::default")]
     pub group: Option<String>,
+
+    /// Move the cleaned tasks to the archive instead of removing them for good.
+    #[serde(default = "bool::default")]
+    pub archive: bool,
+
+    /// Also archive the logs of the cleaned tasks.
+    #[serde(default = "bool::default")]
+    pub archive_logs: bool,
 }
 
 /// Determines which type of shutdown we're dealing with.
@@ -211,6 +421,302 @@ pub struct StreamRequestMessage {
     pub err: bool,
 }
 
+/// Follow the output of several tasks at once.
+///
+/// `task_ids` Follow these tasks. If empty, all tasks are followed. \
+/// `kinds` Follow these outputs of each task. If empty, stdout and stderr are followed. \
+/// `offsets` Resume outputs from these byte offsets, e.g. after a reconnect.
+/// Output before an offset isn't sent again. All other outputs are sent from their beginning.
+#[derive(PartialEq, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MultiStreamRequestMessage {
+    pub task_ids: Vec<usize>,
+    pub kinds: Vec<OutputKind>,
+    pub offsets: Vec<StreamOffset>,
+}
+
+impl MultiStreamRequestMessage {
+    /// Check whether an output of a task is followed.
+    pub fn follows(&self, task_id: usize, kind: OutputKind) -> bool {
+        (self.task_ids.is_empty() || self.task_ids.contains(&task_id))
+            && (self.kinds.is_empty() || self.kinds.contains(&kind))
+    }
+
+    /// The byte offset from which an output of a task has to be sent.
+    pub fn offset(&self, task_id: usize, kind: OutputKind) -> u64 {
+        self.offsets
+            .iter()
+            .find(|offset| offset.task_id == task_id && offset.kind == kind)
+            .map(|offset| offset.offset)
+            .unwrap_or(0)
+    }
+
+    /// Remember the end of a received chunk.
+    /// Sending this request again resumes the output right after the chunk.
+    pub fn advance(&mut self, chunk: &StreamChunkMessage) {
+        let end = chunk.offset + chunk.data.len() as u64;
+        match self
+            .offsets
+            .iter_mut()
+            .find(|offset| offset.task_id == chunk.task_id && offset.kind == chunk.kind)
+        {
+            Some(offset) => offset.offset = offset.offset.max(end),
+            None => self.offsets.push(StreamOffset {
+                task_id: chunk.task_id,
+                kind: chunk.kind,
+                offset: end,
+            }),
+        }
+    }
+}
+
+/// The byte offset in an output of a task.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct StreamOffset {
+    pub task_id: usize,
+    pub kind: OutputKind,
+    pub offset: u64,
+}
+
+/// A piece of raw output of a followed task.
+///
+/// `offset` is the position of `data` in the output. \
+/// The last chunk of an output has `last` set. It's sent once the task finished and may be empty.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct StreamChunkMessage {
+    pub task_id: usize,
+    pub kind: OutputKind,
+    pub offset: u64,
+    pub data: Vec<u8>,
+    pub last: bool,
+}
+
+/// `timeout` is the maximum number of seconds the daemon waits, until it gives up.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct WaitMessage {
+    pub tasks: TaskSelection,
+    pub condition: WaitCondition,
+    pub timeout: Option<u64>,
+}
+
+/// The condition a [WaitMessage] waits for.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum WaitCondition {
+    /// The task is done, no matter the result.
+    #[default]
+    Finished,
+    /// The task has been started. Finished tasks have been started as well.
+    Started,
+    /// The task finished successfully.
+    Succeeded,
+}
+
+impl WaitCondition {
+    /// Check whether a task with the given status reached this condition.
+    pub fn is_reached(&self, status: &TaskStatus) -> bool {
+        match self {
+            WaitCondition::Finished => matches!(status, TaskStatus::Done(_)),
+            WaitCondition::Started => match status {
+                TaskStatus::Running | TaskStatus::Paused => true,
+                TaskStatus::Done(result) => was_started(result),
+                _ => false,
+            },
+            WaitCondition::Succeeded => matches!(status, TaskStatus::Done(TaskResult::Success)),
+        }
+    }
+
+    /// Check whether a task with the given status will never reach this condition,
+    /// e.g. a failed task when waiting for success.
+    pub fn is_unreachable(&self, status: &TaskStatus) -> bool {
+        match (self, status) {
+            (WaitCondition::Finished, _) => false,
+            (WaitCondition::Started, TaskStatus::Done(result)) => !was_started(result),
+            (WaitCondition::Succeeded, TaskStatus::Done(result)) => result != &TaskResult::Success,
+            _ => false,
+        }
+    }
+}
+
+/// Tasks that couldn't be spawned or whose dependencies failed have never been started.
+fn was_started(result: &TaskResult) -> bool {
+    !matches!(
+        result,
+        TaskResult::FailedToSpawn(_) | TaskResult::DependencyFailed
+    )
+}
+
+/// A task reached the condition of a [WaitMessage] or never can.
+/// The status contains the [TaskResult] of finished tasks.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct WaitUpdateMessage {
+    pub task_id: usize,
+    pub status: TaskStatus,
+    pub reached: bool,
+}
+
+/// A batch of requests.
+///
+/// The messages are handled in order and each one gets its own response.
+/// [AddMessage]s can depend on tasks that are created by earlier items of the same batch via
+/// their `batch_dependencies`.
+///
+/// If `atomic` is set, the batch is handled all-or-nothing. \
+/// If any of the messages fails, none of them are applied.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct BatchMessage {
+    pub messages: Vec<Message>,
+    pub atomic: bool,
+}
+
+impl BatchMessage {
+    /// Check whether the batch is well-formed. Otherwise, a [Error::Failure] with
+    /// [ErrorCode::InvalidRequest] is returned. \
+    /// Batches cannot be nested and `batch_dependencies` may only point to earlier
+    /// [Message::Add] items.
+    pub fn validate(&self) -> Result<(), Error> {
+        for (index, message) in self.messages.iter().enumerate() {
+            match message {
+                Message::Batch(_) => {
+                    return Err(invalid_batch("Batches cannot be nested.".into()));
+                }
+                Message::Add(add_message) => {
+                    for dependency in add_message.batch_dependencies.iter() {
+                        let valid = *dependency < index
+                            && matches!(self.messages[*dependency], Message::Add(_));
+                        if !valid {
+                            return Err(invalid_batch(format!(
+                                "Batch item {} can only depend on earlier add items, not on {}.",
+                                index, dependency
+                            )));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid_batch(text: String) -> Error {
+    Error::Failure(FailureMessage {
+        code: ErrorCode::InvalidRequest,
+        task_ids: Vec::new(),
+        text,
+    })
+}
+
+/// Subscribe to events of the daemon.
+///
+/// `task_ids` Only send events of these tasks. Group and daemon events aren't affected. \
+/// `groups` Only send events of tasks in these groups and of these groups themselves. \
+/// `kinds` Only send events of these kinds.
+///
+/// Empty filters match everything.
+///
+/// The daemon confirms the subscription with a [Message::Success] and then sends a
+/// [Message::Event] for each matching event.
+#[derive(PartialEq, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SubscribeMessage {
+    pub task_ids: Vec<usize>,
+    pub groups: Vec<String>,
+    pub kinds: Vec<EventKind>,
+}
+
+impl SubscribeMessage {
+    /// Check whether an event matches the filters of this subscription.
+    pub fn matches(&self, event: &Event) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&event.kind()) {
+            return false;
+        }
+
+        // Group and daemon events are only filtered by their group and kind.
+        if let Some(task_id) = event.task_id() {
+            if !self.task_ids.is_empty() && !self.task_ids.contains(&task_id) {
+                return false;
+            }
+        }
+
+        match event.group() {
+            Some(group) => self.groups.is_empty() || self.groups.iter().any(|name| name == group),
+            None => true,
+        }
+    }
+}
+
+/// Events that are pushed to subscribed clients.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub enum Event {
+    TaskAdded(Task),
+    TaskStatusChanged {
+        task_id: usize,
+        group: String,
+        previous_status: TaskStatus,
+        status: TaskStatus,
+    },
+    TaskRemoved {
+        task_id: usize,
+        group: String,
+    },
+    /// A group has been added or changed.
+    /// `group` is `None`, if the group has been removed.
+    GroupChanged {
+        name: String,
+        group: Option<Group>,
+    },
+    DaemonShutdown(Shutdown),
+}
+
+/// The different kinds of [Event]s, which can be used to filter subscriptions.
+#[derive(PartialEq, Clone, Copy, Debug, Deserialize, Serialize, Display, EnumString)]
+pub enum EventKind {
+    #[strum(serialize = "TaskAdded", serialize = "task_added")]
+    TaskAdded,
+    #[strum(serialize = "TaskStatusChanged", serialize = "task_status_changed")]
+    TaskStatusChanged,
+    #[strum(serialize = "TaskRemoved", serialize = "task_removed")]
+    TaskRemoved,
+    #[strum(serialize = "GroupChanged", serialize = "group_changed")]
+    GroupChanged,
+    #[strum(serialize = "DaemonShutdown", serialize = "daemon_shutdown")]
+    DaemonShutdown,
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::TaskAdded(_) => EventKind::TaskAdded,
+            Event::TaskStatusChanged { .. } => EventKind::TaskStatusChanged,
+            Event::TaskRemoved { .. } => EventKind::TaskRemoved,
+            Event::GroupChanged { .. } => EventKind::GroupChanged,
+            Event::DaemonShutdown(_) => EventKind::DaemonShutdown,
+        }
+    }
+
+    /// The id of the task this event is about, if it's a task event.
+    pub fn task_id(&self) -> Option<usize> {
+        match self {
+            Event::TaskAdded(task) => Some(task.id),
+            Event::TaskStatusChanged { task_id, .. } | Event::TaskRemoved { task_id, .. } => {
+                Some(*task_id)
+            }
+            _ => None,
+        }
+    }
+
+    /// The group this event is about. \
+    /// For task events, this is the task's group.
+    pub fn group(&self) -> Option<&str> {
+        match self {
+            Event::TaskAdded(task) => Some(&task.group),
+            Event::TaskStatusChanged { group, .. }
+            | Event::TaskRemoved { group, .. }
+            | Event::GroupChanged { name: group, .. } => Some(group),
+            Event::DaemonShutdown(_) => None,
+        }
+    }
+}
+
 /// Request logs for specific tasks.
 ///
 /// `task_ids` specifies the requested tasks. If none are given, all tasks are selected.
@@ -231,12 +737,270 @@ pub struct TaskLogMessage {
     pub stderr: Option<Vec<u8>>,
 }
 
+/// A part of a chunked log response.
+///
+/// The first chunk of each task contains the `task`. All following chunks of that task contain
+/// the next part of its [snap] compressed `stdout` and `stderr`. \
+/// The last chunk of the whole response has `last` set.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct LogChunkMessage {
+    pub task_id: usize,
+    pub task: Option<Task>,
+    pub stdout: Option<Vec<u8>>,
+    pub stderr: Option<Vec<u8>>,
+    pub last: bool,
+}
+
+/// Split a log response into chunks, whose log output is at most `chunk_size` bytes each.
+pub fn split_log_response(
+    logs: BTreeMap<usize, TaskLogMessage>,
+    chunk_size: usize,
+) -> Vec<LogChunkMessage> {
+    let mut chunks = Vec::new();
+    for (task_id, log) in logs.into_iter() {
+        // The first chunk of a task also determines whether its output has been sent at all.
+        chunks.push(LogChunkMessage {
+            task_id,
+            stdout: log.stdout.as_ref().map(|_| Vec::new()),
+            stderr: log.stderr.as_ref().map(|_| Vec::new()),
+            task: Some(log.task),
+            last: false,
+        });
+
+        for output in log
+            .stdout
+            .iter()
+            .flat_map(|stdout| stdout.chunks(chunk_size))
+        {
+            chunks.push(LogChunkMessage {
+                task_id,
+                task: None,
+                stdout: Some(output.to_vec()),
+                stderr: None,
+                last: false,
+            });
+        }
+        for output in log
+            .stderr
+            .iter()
+            .flat_map(|stderr| stderr.chunks(chunk_size))
+        {
+            chunks.push(LogChunkMessage {
+                task_id,
+                task: None,
+                stdout: None,
+                stderr: Some(output.to_vec()),
+                last: false,
+            });
+        }
+    }
+
+    if let Some(chunk) = chunks.last_mut() {
+        chunk.last = true;
+    }
+
+    chunks
+}
+
+/// Add a chunk of a chunked log response to the already received logs.
+pub fn merge_log_chunk(
+    logs: &mut BTreeMap<usize, TaskLogMessage>,
+    chunk: LogChunkMessage,
+) -> Result<(), Error> {
+    if let Some(task) = chunk.task {
+        logs.insert(
+            chunk.task_id,
+            TaskLogMessage {
+                task,
+                stdout: None,
+                stderr: None,
+            },
+        );
+    }
+
+    let log = logs.get_mut(&chunk.task_id).ok_or_else(|| {
+        Error::MessageDeserialization(format!(
+            "Got log chunk for task {} before its task.",
+            chunk.task_id
+        ))
+    })?;
+    if let Some(stdout) = chunk.stdout {
+        log.stdout.get_or_insert_with(Vec::new).extend(stdout);
+    }
+    if let Some(stderr) = chunk.stderr {
+        log.stderr.get_or_insert_with(Vec::new).extend(stderr);
+    }
+
+    Ok(())
+}
+
+/// The result of a request that affects several tasks, e.g. starting tasks.
+///
+/// `succeeded` are the tasks the request has been applied to. \
+/// `failures` lists the tasks the request couldn't be applied to, grouped by reason. \
+/// `text` is a human readable summary, e.g. "Tasks are being started: 0, 1, 2. Tasks are
+/// already running: 3, 4".
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct PartialSuccessMessage {
+    pub succeeded: Vec<usize>,
+    pub failures: Vec<FailureMessage>,
+    pub text: String,
+}
+
+impl PartialSuccessMessage {
+    /// Whether the request could be applied to all tasks.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// All tasks the request couldn't be applied to.
+    pub fn failed_task_ids(&self) -> Vec<usize> {
+        self.failures
+            .iter()
+            .flat_map(|failure| failure.task_ids.iter().cloned())
+            .collect()
+    }
+}
+
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
 pub struct ParallelMessage {
     pub parallel_tasks: usize,
     pub group: String,
 }
 
+/// The first thing that's exchanged by both sides of a new connection.
+///
+/// `version` is the newest protocol version the sender speaks.
+/// `min_version` is the oldest protocol version the sender is still compatible with.
+/// `features` is the list of optional features the sender supports.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct Handshake {
+    pub version: u32,
+    pub min_version: u32,
+    pub features: Vec<String>,
+}
+
+/// A message with the id of the request it belongs to.
+///
+/// Envelopes are used on connections with the multiplexing feature.
+/// The client chooses a unique id for each request and the daemon uses the same id for all of its
+/// responses to that request. That way, multiple requests can be in flight on a single connection.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct Envelope {
+    pub request_id: u64,
+    pub message: Message,
+}
+
+/// Escalations with signals that aren't understood by all versions need the `signals` feature.
+fn escalation_signals_feature(escalation: &KillEscalation) -> Option<&'static str> {
+    if escalation.signal.is_legacy() && escalation.follow_up.is_legacy() {
+        return None;
+    }
+
+    Some(crate::network::protocol::FEATURE_SIGNALS)
+}
+
+impl Message {
+    /// The protocol features that are needed by the other side to understand this message. \
+    /// Messages that have been part of the protocol from the start don't require any feature.
+    /// The items of a [Message::Batch] and [Message::BatchResponse] need their features as well.
+    pub fn required_features(&self) -> Vec<&'static str> {
+        use crate::network::protocol::*;
+
+        let mut features = Vec::new();
+        match self {
+            Message::Add(message) => {
+                if let Some(escalation) = &message.kill_escalation {
+                    features.push(FEATURE_KILL_ESCALATION);
+                    features.extend(escalation_signals_feature(escalation));
+                }
+            }
+            Message::Kill(message) => {
+                if let Some(escalation) = &message.escalation {
+                    features.push(FEATURE_KILL_ESCALATION);
+                    features.extend(escalation_signals_feature(escalation));
+                }
+                if message.process_group
+                    || message
+                        .signal
+                        .as_ref()
+                        .is_some_and(|signal| !signal.is_legacy())
+                {
+                    features.push(FEATURE_SIGNALS);
+                }
+            }
+            Message::PartialEdit(message) => {
+                features.push(FEATURE_TASK_EDIT);
+                for change in message.changes.iter() {
+                    if let TaskChange::KillEscalation(Some(escalation)) = change {
+                        features.push(FEATURE_KILL_ESCALATION);
+                        features.extend(escalation_signals_feature(escalation));
+                    }
+                }
+            }
+            Message::MoveToGroup(_) => features.push(FEATURE_MOVE_TO_GROUP),
+            Message::Group(GroupMessage::Edit { .. })
+            | Message::Group(GroupMessage::SetParent { .. })
+            | Message::Group(GroupMessage::Rename { .. }) => features.push(FEATURE_GROUP_EDIT),
+            Message::ArchiveQuery(_) | Message::ArchiveResponse(_) | Message::ArchiveRestore(_) => {
+                features.push(FEATURE_ARCHIVE)
+            }
+            Message::Subscribe(_) | Message::Event(_) => features.push(FEATURE_EVENTS),
+            Message::Batch(BatchMessage { messages, .. }) | Message::BatchResponse(messages) => {
+                features.push(FEATURE_BATCH);
+                for message in messages.iter() {
+                    features.extend(message.required_features());
+                }
+            }
+            Message::LogChunk(_) => features.push(FEATURE_LOG_CHUNKS),
+            Message::Wait(_) | Message::WaitUpdate(_) => features.push(FEATURE_WAIT),
+            Message::Env(_) | Message::EnvResponse(_) => features.push(FEATURE_ENV),
+            Message::MultiStreamRequest(_) | Message::StreamChunk(_) => {
+                features.push(FEATURE_MULTI_STREAM)
+            }
+            Message::StructuredFailure(_) | Message::PartialSuccess(_) => {
+                features.push(FEATURE_ERROR_CODES)
+            }
+            _ => (),
+        }
+
+        features.sort_unstable();
+        features.dedup();
+        features
+    }
+
+    /// Convert failure responses into an [Error::Failure]. \
+    /// Plain [Message::Failure]s get the [ErrorCode::Other] code.
+    pub fn into_result(self) -> Result<Message, Error> {
+        match self {
+            Message::Failure(text) => Err(Error::Failure(FailureMessage {
+                code: ErrorCode::Other,
+                task_ids: Vec::new(),
+                text,
+            })),
+            Message::StructuredFailure(failure) => Err(Error::Failure(failure)),
+            message => Ok(message),
+        }
+    }
+
+    /// Convert structured responses to the plain [Message::Success] and [Message::Failure]
+    /// messages, which are understood by all clients. \
+    /// Partial successes are failures, if the request couldn't be applied to any task.
+    /// All other messages are returned as they are.
+    pub fn into_legacy(self) -> Message {
+        match self {
+            Message::StructuredFailure(failure) => Message::Failure(failure.text),
+            Message::PartialSuccess(result)
+                if result.succeeded.is_empty() && !result.is_success() =>
+            {
+                Message::Failure(result.text)
+            }
+            Message::PartialSuccess(result) => Message::Success(result.text),
+            message => message,
+        }
+    }
+}
+
 pub fn create_success_message<T: ToString>(text: T) -> Message {
     Message::Success(text.to_string())
 }
@@ -244,3 +1008,291 @@ pub fn create_success_message<T: ToString>(text: T) -> Message {
 pub fn create_failure_message<T: ToString>(text: T) -> Message {
     Message::Failure(text.to_string())
 }
+
+pub fn create_structured_failure_message<T: ToString>(
+    code: ErrorCode,
+    task_ids: Vec<usize>,
+    text: T,
+) -> Message {
+    Message::StructuredFailure(FailureMessage {
+        code,
+        task_ids,
+        text: text.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::network::protocol::{
+        local_handshake, negotiate, FEATURE_BATCH, FEATURE_KILL_ESCALATION, FEATURE_SIGNALS,
+    };
+    use crate::state::PUEUE_DEFAULT_GROUP;
+    use crate::test_helper;
+
+    #[test]
+    fn test_subscription_filter() {
+        let status_changed = Event::TaskStatusChanged {
+            task_id: 1,
+            group: PUEUE_DEFAULT_GROUP.into(),
+            previous_status: TaskStatus::Queued,
+            status: TaskStatus::Running,
+        };
+        let shutdown = Event::DaemonShutdown(Shutdown::Graceful);
+        let group_changed = Event::GroupChanged {
+            name: PUEUE_DEFAULT_GROUP.into(),
+            group: None,
+        };
+
+        assert!(SubscribeMessage::default().matches(&status_changed));
+
+        let subscription = SubscribeMessage {
+            task_ids: vec![1],
+            ..Default::default()
+        };
+        assert!(subscription.matches(&status_changed));
+        assert!(subscription.matches(&shutdown));
+        assert!(subscription.matches(&group_changed));
+
+        let subscription = SubscribeMessage {
+            task_ids: vec![2],
+            groups: vec![PUEUE_DEFAULT_GROUP.into()],
+            ..Default::default()
+        };
+        assert!(!subscription.matches(&status_changed));
+        assert!(subscription.matches(&group_changed));
+
+        let subscription = SubscribeMessage {
+            groups: vec!["other".into()],
+            ..Default::default()
+        };
+        assert!(!subscription.matches(&status_changed));
+        assert!(!subscription.matches(&group_changed));
+        assert!(subscription.matches(&shutdown));
+
+        let subscription = SubscribeMessage {
+            kinds: vec![EventKind::TaskRemoved],
+            ..Default::default()
+        };
+        assert!(!subscription.matches(&status_changed));
+    }
+
+    fn add_message(batch_dependencies: Vec<usize>) -> Message {
+        Message::Add(AddMessage {
+            dependencies: vec![0],
+            batch_dependencies,
+            ..test_helper::add_message(PUEUE_DEFAULT_GROUP)
+        })
+    }
+
+    #[test]
+    fn test_batch_dependencies() {
+        let batch = BatchMessage {
+            messages: vec![add_message(vec![]), Message::Status, add_message(vec![0])],
+            atomic: true,
+        };
+        assert!(batch.validate().is_ok());
+
+        // Task 7 has been created by the first item.
+        let mut created = BTreeMap::new();
+        created.insert(0, 7);
+        if let Message::Add(mut add_message) = batch.messages[2].clone() {
+            add_message.resolve_batch_dependencies(&created).unwrap();
+            assert_eq!(add_message.dependencies, vec![0, 7]);
+            assert!(add_message.batch_dependencies.is_empty());
+        }
+
+        // References to later items or non-add items are invalid.
+        for dependency in [1, 2] {
+            let batch = BatchMessage {
+                messages: vec![
+                    add_message(vec![]),
+                    Message::Status,
+                    add_message(vec![dependency]),
+                ],
+                atomic: false,
+            };
+            assert!(matches!(
+                batch.validate(),
+                Err(Error::Failure(FailureMessage {
+                    code: ErrorCode::InvalidRequest,
+                    ..
+                }))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_structured_responses_into_legacy() {
+        let failure = create_structured_failure_message(
+            ErrorCode::TaskNotFound,
+            vec![5],
+            "Task 5 doesn't exist",
+        );
+        assert_eq!(
+            failure.into_legacy(),
+            create_failure_message("Task 5 doesn't exist")
+        );
+
+        let mut result = PartialSuccessMessage {
+            succeeded: vec![0],
+            failures: vec![FailureMessage {
+                code: ErrorCode::TaskRunning,
+                task_ids: vec![3, 4],
+                text: "Tasks are already running: 3, 4".into(),
+            }],
+            text: "Started 1 task, 2 were already running".into(),
+        };
+        assert_eq!(result.failed_task_ids(), vec![3, 4]);
+        assert_eq!(
+            Message::PartialSuccess(result.clone()).into_legacy(),
+            create_success_message("Started 1 task, 2 were already running")
+        );
+
+        // Nothing succeeded at all.
+        result.succeeded.clear();
+        assert!(matches!(
+            Message::PartialSuccess(result).into_legacy(),
+            Message::Failure(_)
+        ));
+    }
+
+    #[test]
+    fn test_multi_stream_offsets() {
+        let mut request = MultiStreamRequestMessage {
+            task_ids: vec![1],
+            kinds: vec![OutputKind::Stderr],
+            offsets: Vec::new(),
+        };
+        assert!(request.follows(1, OutputKind::Stderr));
+        assert!(!request.follows(1, OutputKind::Stdout));
+        assert!(!request.follows(2, OutputKind::Stderr));
+
+        let mut chunk = StreamChunkMessage {
+            task_id: 1,
+            kind: OutputKind::Stderr,
+            offset: 0,
+            data: b"some output".to_vec(),
+            last: false,
+        };
+        request.advance(&chunk);
+        assert_eq!(request.offset(1, OutputKind::Stderr), 11);
+        assert_eq!(request.offset(1, OutputKind::Stdout), 0);
+
+        // Chunks that are received twice don't move the offset back.
+        chunk.data.truncate(4);
+        request.advance(&chunk);
+        assert_eq!(request.offset(1, OutputKind::Stderr), 11);
+    }
+
+    #[test]
+    fn test_required_features() {
+        let kill = |signal, process_group, escalation| {
+            Message::Kill(KillMessage {
+                tasks: TaskSelection::All,
+                children: false,
+                signal,
+                process_group,
+                escalation,
+            })
+        };
+
+        assert!(kill(Some(Signal::SigTerm), false, None)
+            .required_features()
+            .is_empty());
+        assert_eq!(
+            kill(Some(Signal::SigUsr2), false, None).required_features(),
+            vec![FEATURE_SIGNALS]
+        );
+        assert_eq!(
+            kill(None, true, None).required_features(),
+            vec![FEATURE_SIGNALS]
+        );
+        assert_eq!(
+            kill(None, false, Some(KillEscalation::default())).required_features(),
+            vec![FEATURE_KILL_ESCALATION]
+        );
+
+        // All features are required, not only the first one.
+        let escalation = KillEscalation {
+            signal: Signal::SigHup,
+            ..Default::default()
+        };
+        assert_eq!(
+            kill(None, true, Some(escalation.clone())).required_features(),
+            vec![FEATURE_KILL_ESCALATION, FEATURE_SIGNALS]
+        );
+
+        // The items of a batch need their features as well.
+        let add = Message::Add(AddMessage {
+            kill_escalation: Some(escalation),
+            ..test_helper::add_message(PUEUE_DEFAULT_GROUP)
+        });
+        let batch = Message::Batch(BatchMessage {
+            messages: vec![add],
+            atomic: true,
+        });
+        assert_eq!(
+            batch.required_features(),
+            vec![FEATURE_BATCH, FEATURE_KILL_ESCALATION, FEATURE_SIGNALS]
+        );
+    }
+
+    #[test]
+    /// An empty group doesn't move the task to a group without a name.
+    /// The enqueue time is only changed for stashed tasks.
+    fn test_edit_changes() {
+        let capabilities = negotiate(&local_handshake(), &local_handshake()).unwrap();
+        let edit = EditMessage {
+            task_id: 0,
+            command: "ls".into(),
+            path: "/tmp".into(),
+            label: None,
+            envs: HashMap::new(),
+            group: String::new(),
+            dependencies: Vec::new(),
+            enqueue_at: None,
+        };
+
+        let changes = edit
+            .clone()
+            .into_changes(&capabilities, &TaskStatus::Queued);
+        assert!(!changes
+            .iter()
+            .any(|change| matches!(change, TaskChange::Group(_) | TaskChange::EnqueueAt(_))));
+
+        // Stashed tasks may lose their enqueue time.
+        let stashed = TaskStatus::Stashed {
+            enqueue_at: Some(Local::now()),
+        };
+        let changes = edit.clone().into_changes(&capabilities, &stashed);
+        assert!(changes.contains(&TaskChange::EnqueueAt(None)));
+
+        let edit = EditMessage {
+            group: "other".into(),
+            ..edit
+        };
+        let changes = edit.into_changes(&capabilities, &TaskStatus::Queued);
+        assert!(changes.contains(&TaskChange::Group("other".into())));
+    }
+
+    #[test]
+    fn test_wait_condition() {
+        let failed = TaskStatus::Done(TaskResult::Failed(1));
+        let not_spawned = TaskStatus::Done(TaskResult::FailedToSpawn("no such file".into()));
+
+        assert!(!WaitCondition::Finished.is_reached(&TaskStatus::Running));
+        assert!(WaitCondition::Finished.is_reached(&failed));
+
+        assert!(!WaitCondition::Started.is_reached(&TaskStatus::Queued));
+        assert!(WaitCondition::Started.is_reached(&TaskStatus::Paused));
+        assert!(WaitCondition::Started.is_reached(&failed));
+        assert!(WaitCondition::Started.is_unreachable(&not_spawned));
+
+        assert!(WaitCondition::Succeeded.is_reached(&TaskStatus::Done(TaskResult::Success)));
+        assert!(!WaitCondition::Succeeded.is_reached(&failed));
+        assert!(WaitCondition::Succeeded.is_unreachable(&failed));
+        assert!(!WaitCondition::Succeeded.is_unreachable(&TaskStatus::Running));
+    }
+}
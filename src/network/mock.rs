@@ -0,0 +1,1088 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::Local;
+use log::debug;
+use snap::write::FrameEncoder;
+use tempdir::TempDir;
+use tokio::sync::watch;
+use tokio::task::{self, JoinHandle};
+use tokio::time::{timeout_at, Instant};
+
+use crate::error::Error;
+use crate::network::message::*;
+use crate::network::protocol::*;
+use crate::network::secret::{init_shared_secret, read_shared_secret};
+use crate::settings::{Settings, Shared};
+use crate::state::State;
+use crate::task::{Task, TaskResult, TaskStatus};
+
+/// A fake daemon for integration tests, which runs inside the test process.
+///
+/// It listens on a unix socket in a temporary directory and keeps its [State] in memory.
+/// No processes are ever spawned. Instead, tests script what happens to tasks via
+/// [MockDaemon::set_status], [MockDaemon::set_output] and [MockDaemon::update_state].
+///
+/// The daemon answers `Add`, `Status`, `Kill`, `Log`, `Group`, `MoveToGroup`, `Env`, `Wait`,
+/// `MultiStreamRequest`, `Subscribe`, `Batch` and all edit messages like the real daemon.
+/// All other messages are rejected with [ErrorCode::InvalidRequest]. \
+/// The daemon is shut down, once the [MockDaemon] is dropped.
+pub struct MockDaemon {
+    settings: Shared,
+    inner: Arc<Mutex<MockState>>,
+    listener: JoinHandle<()>,
+    // The directory is removed on drop, so it has to outlive the daemon.
+    _tempdir: TempDir,
+}
+
+/// How the mock daemon killed a task.
+#[derive(PartialEq, Clone, Debug)]
+pub struct MockKill {
+    /// The signal that has been sent without an escalation, if any.
+    pub signal: Option<Signal>,
+    /// The escalation of the kill request or, if there's none, the task's default escalation.
+    pub escalation: Option<KillEscalation>,
+    pub process_group: bool,
+    pub children: bool,
+}
+
+/// The in-memory state of the mock daemon and the scripted output of its tasks.
+struct MockState {
+    state: State,
+    outputs: BTreeMap<usize, (String, String)>,
+    /// The last kill of each task.
+    kills: BTreeMap<usize, MockKill>,
+    /// Notifies waiting connections about changes of the state.
+    changes: watch::Sender<()>,
+}
+
+impl MockDaemon {
+    /// Start a new mock daemon with an empty state. \
+    /// This has to be called inside of a tokio runtime.
+    pub async fn start() -> Result<MockDaemon, Error> {
+        let tempdir = TempDir::new("pueue_mock")?;
+        let path = tempdir.path();
+
+        let (mut settings, _) = Settings::read_with_defaults(&None)?;
+        settings.shared = Shared {
+            pueue_directory: path.to_path_buf(),
+            use_unix_socket: true,
+            unix_socket_path: path.join("pueue.socket"),
+            host: "localhost".into(),
+            port: "0".into(),
+            daemon_cert: path.join("daemon.cert"),
+            daemon_key: path.join("daemon.key"),
+            shared_secret_path: path.join("secret"),
+        };
+        init_shared_secret(&settings.shared.shared_secret_path())?;
+
+        let secret = read_shared_secret(&settings.shared.shared_secret_path())?;
+        let listener = get_listener(&settings.shared).await?;
+        let inner = Arc::new(Mutex::new(MockState {
+            state: State::new(&settings, None),
+            outputs: BTreeMap::new(),
+            kills: BTreeMap::new(),
+            changes: watch::channel(()).0,
+        }));
+
+        let connection_state = inner.clone();
+        let listener = task::spawn(async move {
+            while let Ok(stream) = listener.accept().await {
+                let inner = connection_state.clone();
+                let secret = secret.clone();
+                task::spawn(async move {
+                    if let Err(err) = handle_connection(stream, secret, inner).await {
+                        debug!("Mock daemon connection closed: {}", err);
+                    }
+                });
+            }
+        });
+
+        Ok(MockDaemon {
+            settings: settings.shared,
+            inner,
+            listener,
+            _tempdir: tempdir,
+        })
+    }
+
+    /// The settings, which are needed to connect to this daemon.
+    pub fn settings(&self) -> &Shared {
+        &self.settings
+    }
+
+    /// Get a copy of the daemon's current state.
+    pub fn state(&self) -> State {
+        self.lock().state.clone()
+    }
+
+    /// Modify the daemon's state in arbitrary ways.
+    pub fn update_state<T>(&self, update: impl FnOnce(&mut State) -> T) -> T {
+        let mut inner = self.lock();
+        let result = update(&mut inner.state);
+        inner.changes.send_replace(());
+
+        result
+    }
+
+    /// Move a task to a new status, e.g. `TaskStatus::Done(TaskResult::Failed(1))`. \
+    /// The start and end times of the task are set, as the real daemon would do it.
+    pub fn set_status(&self, task_id: usize, status: TaskStatus) -> Result<(), Error> {
+        let mut inner = self.lock();
+        let task = inner
+            .state
+            .tasks
+            .get_mut(&task_id)
+            .ok_or_else(|| task_not_found(task_id))?;
+        set_task_status(task, status);
+        inner.changes.send_replace(());
+
+        Ok(())
+    }
+
+    /// Set the output of a task, which is returned for log requests. \
+    /// Followed outputs are streamed from their last offset, so streamed output should only grow.
+    pub fn set_output(&self, task_id: usize, stdout: &str, stderr: &str) -> Result<(), Error> {
+        let mut inner = self.lock();
+        if !inner.state.tasks.contains_key(&task_id) {
+            return Err(task_not_found(task_id));
+        }
+        inner
+            .outputs
+            .insert(task_id, (stdout.to_string(), stderr.to_string()));
+        inner.changes.send_replace(());
+
+        Ok(())
+    }
+
+    /// Get how a task has been killed the last time, if it has been killed at all.
+    pub fn last_kill(&self, task_id: usize) -> Option<MockKill> {
+        self.lock().kills.get(&task_id).cloned()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        lock(&self.inner)
+    }
+}
+
+impl Drop for MockDaemon {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+/// Authenticate a client, do the handshake and answer its messages until it disconnects.
+async fn handle_connection(
+    mut stream: GenericStream,
+    secret: Vec<u8>,
+    inner: Arc<Mutex<MockState>>,
+) -> Result<(), Error> {
+    if receive_bytes(&mut stream).await? != secret {
+        return Err(Error::Connection("Received invalid secret".into()));
+    }
+    send_bytes(env!("CARGO_PKG_VERSION").as_bytes(), &mut stream).await?;
+    let capabilities = daemon_handshake(&mut stream).await?;
+
+    loop {
+        let message = receive_message_with(&capabilities, &mut stream).await?;
+        if let Message::Log(request) = message {
+            // Errors are reported to the client instead of ending the connection.
+            let logs = get_logs(request, &lock(&inner));
+            match logs {
+                Ok(logs) => send_log_response(logs, &capabilities, &mut stream).await?,
+                Err(err) => {
+                    let failure = create_structured_failure_message(
+                        ErrorCode::Other,
+                        Vec::new(),
+                        format!("Failed to read logs: {}", err),
+                    );
+                    send_response(failure, &capabilities, &mut stream).await?
+                }
+            }
+            continue;
+        }
+
+        if let Message::Wait(message) = message {
+            wait(message, &capabilities, &mut stream, &inner).await?;
+            continue;
+        }
+        if let Message::MultiStreamRequest(request) = message {
+            stream_outputs(request, &capabilities, &mut stream, &inner).await?;
+            continue;
+        }
+        if let Message::Subscribe(subscription) = message {
+            // Subscribed connections only receive events from now on.
+            return subscribe(subscription, &capabilities, &mut stream, &inner).await;
+        }
+
+        let response = {
+            let mut inner = lock(&inner);
+            let response = handle_message(message, &capabilities, &mut inner);
+            inner.changes.send_replace(());
+            response
+        };
+        send_response(response, &capabilities, &mut stream).await?;
+    }
+}
+
+async fn send_response(
+    mut response: Message,
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+) -> Result<(), Error> {
+    if !capabilities.supports(FEATURE_ERROR_CODES) {
+        response = response.into_legacy();
+    }
+
+    send_message_with(response, capabilities, stream).await
+}
+
+/// Send an update for each task, once it reached the condition or never can.
+/// This is checked again whenever the state changes, until all tasks are handled or the wait
+/// timed out.
+async fn wait(
+    message: WaitMessage,
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+    inner: &Mutex<MockState>,
+) -> Result<(), Error> {
+    let deadline = message
+        .timeout
+        .map(|timeout| Instant::now() + Duration::from_secs(timeout));
+
+    let selection = {
+        let inner = lock(inner);
+        match check_selection(&message.tasks, &inner.state) {
+            Some(failure) => Err(failure),
+            None => Ok((
+                inner.state.task_ids_in_selection(&message.tasks),
+                inner.changes.subscribe(),
+            )),
+        }
+    };
+    let (mut pending, mut changes) = match selection {
+        Ok(selection) => selection,
+        Err(failure) => return send_response(failure, capabilities, stream).await,
+    };
+
+    let mut reached = Vec::new();
+    let mut unreachable = Vec::new();
+    let mut removed = Vec::new();
+    loop {
+        let mut updates = Vec::new();
+        {
+            let inner = lock(inner);
+            pending.retain(|task_id| {
+                let status = match inner.state.tasks.get(task_id) {
+                    Some(task) => task.status.clone(),
+                    None => {
+                        removed.push(*task_id);
+                        return false;
+                    }
+                };
+
+                if message.condition.is_reached(&status) {
+                    reached.push(*task_id);
+                } else if message.condition.is_unreachable(&status) {
+                    unreachable.push(*task_id);
+                } else {
+                    return true;
+                }
+                updates.push(WaitUpdateMessage {
+                    task_id: *task_id,
+                    reached: message.condition.is_reached(&status),
+                    status,
+                });
+
+                false
+            });
+        }
+
+        for update in updates {
+            send_message_with(Message::WaitUpdate(update), capabilities, stream).await?;
+        }
+        if pending.is_empty() {
+            break;
+        }
+
+        let changed = changes.changed();
+        let changed = match deadline {
+            Some(deadline) => match timeout_at(deadline, changed).await {
+                Ok(changed) => changed,
+                Err(_) => break,
+            },
+            None => changed.await,
+        };
+        changed.map_err(|_| Error::Generic("Mock daemon state has been dropped".into()))?;
+    }
+
+    let mut failures = Vec::new();
+    for (code, task_ids, text) in [
+        (
+            ErrorCode::ConditionUnreachable,
+            unreachable,
+            "Tasks can no longer reach the condition",
+        ),
+        (
+            ErrorCode::Timeout,
+            pending,
+            "Timed out while waiting for tasks",
+        ),
+        (ErrorCode::TaskNotFound, removed, "Tasks have been removed"),
+    ] {
+        if !task_ids.is_empty() {
+            failures.push(FailureMessage {
+                code,
+                text: format!("{}: {}", text, join(&task_ids)),
+                task_ids,
+            });
+        }
+    }
+
+    let response = if failures.is_empty() {
+        create_success_message("All tasks reached the condition.")
+    } else {
+        Message::PartialSuccess(PartialSuccessMessage {
+            text: failures
+                .iter()
+                .map(|failure| failure.text.clone())
+                .collect::<Vec<String>>()
+                .join("\n"),
+            succeeded: reached,
+            failures,
+        })
+    };
+
+    send_response(response, capabilities, stream).await
+}
+
+fn lock(inner: &Mutex<MockState>) -> MutexGuard<'_, MockState> {
+    inner.lock().expect("Mock daemon state has been poisoned")
+}
+
+fn handle_message(message: Message, capabilities: &Capabilities, inner: &mut MockState) -> Message {
+    match message {
+        Message::EditRequest(task_id) => edit_request(task_id, &mut inner.state),
+        Message::Edit(message) => edit(message, capabilities, &mut inner.state),
+        Message::PartialEdit(message) => {
+            match inner.state.edit_task(message.task_id, message.changes) {
+                Ok(()) => create_success_message("Task has been changed."),
+                Err(err) => failure_response(err),
+            }
+        }
+        Message::Add(message) => add_task(message, &mut inner.state),
+        Message::Status => Message::StatusResponse(Box::new(inner.state.clone())),
+        Message::Kill(message) => kill(message, inner),
+        Message::Group(message) => group(message, &mut inner.state),
+        Message::MoveToGroup(message) => move_to_group(message, &mut inner.state),
+        Message::Batch(message) => batch(message, capabilities, inner),
+        Message::Env(message) => env(message, &mut inner.state),
+        message => create_structured_failure_message(
+            ErrorCode::InvalidRequest,
+            Vec::new(),
+            format!("The mock daemon doesn't support {:?}", message),
+        ),
+    }
+}
+
+fn add_task(message: AddMessage, state: &mut State) -> Message {
+    let print_task_id = message.print_task_id;
+    match create_task(message, state) {
+        Ok(task_id) if print_task_id => create_success_message(task_id),
+        Ok(task_id) => create_success_message(format!("New task added (id {}).", task_id)),
+        Err(err) => failure_response(err),
+    }
+}
+
+/// Create the task of an [AddMessage] and return its id.
+fn create_task(message: AddMessage, state: &mut State) -> Result<usize, Error> {
+    if !state.groups.contains_key(&message.group) {
+        return Err(Error::Failure(FailureMessage {
+            code: ErrorCode::GroupNotFound,
+            task_ids: Vec::new(),
+            text: format!("Group \"{}\" doesn't exist", message.group),
+        }));
+    }
+    let missing: Vec<usize> = message
+        .dependencies
+        .iter()
+        .filter(|id| !state.tasks.contains_key(id))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::Failure(FailureMessage {
+            code: ErrorCode::InvalidDependency,
+            text: format!(
+                "Unable to setup dependencies : task(s) {:?} not found",
+                missing
+            ),
+            task_ids: missing,
+        }));
+    }
+
+    let status = if message.stashed || message.enqueue_at.is_some() {
+        TaskStatus::Stashed {
+            enqueue_at: message.enqueue_at,
+        }
+    } else {
+        TaskStatus::Queued
+    };
+    let mut task = Task::new(
+        message.command,
+        message.path,
+        message.envs,
+        message.group,
+        status,
+        message.dependencies,
+        message.label,
+    );
+    task.kill_escalation = message.kill_escalation;
+    if message.start_immediately {
+        set_task_status(&mut task, TaskStatus::Running);
+    }
+
+    Ok(state.add_task(task))
+}
+
+/// Handle the messages of a batch in order and answer with all of their responses. \
+/// Atomic batches are rolled back completely, as soon as one of their messages fails.
+fn batch(batch: BatchMessage, capabilities: &Capabilities, inner: &mut MockState) -> Message {
+    if let Err(err) = batch.validate() {
+        return failure_response(err);
+    }
+    let previous_state = inner.state.clone();
+    let count = batch.messages.len();
+
+    let mut created_task_ids = BTreeMap::new();
+    let mut responses = Vec::new();
+    for (index, message) in batch.messages.into_iter().enumerate() {
+        let response = match message {
+            Message::Add(mut message) => {
+                match message.resolve_batch_dependencies(&created_task_ids) {
+                    Ok(()) => match create_task(message, &mut inner.state) {
+                        Ok(task_id) => {
+                            created_task_ids.insert(index, task_id);
+                            create_success_message(format!("New task added (id {}).", task_id))
+                        }
+                        Err(err) => failure_response(err),
+                    },
+                    Err(err) => failure_response(err),
+                }
+            }
+            message => handle_message(message, capabilities, inner),
+        };
+
+        let failed = matches!(
+            response,
+            Message::Failure(_) | Message::StructuredFailure(_) | Message::PartialSuccess(_)
+        );
+        if batch.atomic && failed {
+            inner.state = previous_state;
+            let rolled_back = || {
+                create_structured_failure_message(
+                    ErrorCode::InvalidRequest,
+                    Vec::new(),
+                    format!("Batch has been rolled back, as item {} failed.", index),
+                )
+            };
+            let mut responses: Vec<Message> = (0..index).map(|_| rolled_back()).collect();
+            responses.push(response);
+            responses.extend((index + 1..count).map(|_| rolled_back()));
+
+            return Message::BatchResponse(responses);
+        }
+        responses.push(response);
+    }
+
+    Message::BatchResponse(responses)
+}
+
+/// Send new output of the followed tasks, whenever the state changes.
+/// Each output ends with a `last` chunk, once its task finished.
+///
+/// Tasks that are added later on are followed as well, if the request matches them.
+async fn stream_outputs(
+    request: MultiStreamRequestMessage,
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+    inner: &Mutex<MockState>,
+) -> Result<(), Error> {
+    let selection = {
+        let inner = lock(inner);
+        let selection = TaskSelection::TaskIds(request.task_ids.clone());
+        match check_selection(&selection, &inner.state) {
+            Some(failure) => Err(failure),
+            None => Ok(inner.changes.subscribe()),
+        }
+    };
+    let mut changes = match selection {
+        Ok(changes) => changes,
+        Err(failure) => return send_response(failure, capabilities, stream).await,
+    };
+
+    let mut known_task_ids = BTreeSet::new();
+    let mut followed = Vec::new();
+    loop {
+        let mut chunks = Vec::new();
+        {
+            let inner = lock(inner);
+            for task_id in inner.state.tasks.keys() {
+                if !known_task_ids.insert(*task_id) {
+                    continue;
+                }
+                for kind in [OutputKind::Stdout, OutputKind::Stderr] {
+                    if request.follows(*task_id, kind) {
+                        followed.push((*task_id, kind, request.offset(*task_id, kind)));
+                    }
+                }
+            }
+
+            followed.retain_mut(|(task_id, kind, offset)| {
+                let finished = match inner.state.tasks.get(task_id) {
+                    Some(task) => matches!(task.status, TaskStatus::Done(_)),
+                    None => true,
+                };
+                let (stdout, stderr) = inner.outputs.get(task_id).cloned().unwrap_or_default();
+                let output = match kind {
+                    OutputKind::Stdout => stdout.into_bytes(),
+                    OutputKind::Stderr => stderr.into_bytes(),
+                };
+
+                let data = output
+                    .get(*offset as usize..)
+                    .map(|data| data.to_vec())
+                    .unwrap_or_default();
+                if !data.is_empty() || finished {
+                    chunks.push(StreamChunkMessage {
+                        task_id: *task_id,
+                        kind: *kind,
+                        offset: *offset,
+                        last: finished,
+                        data,
+                    });
+                }
+                *offset = (*offset).max(output.len() as u64);
+
+                !finished
+            });
+        }
+
+        for chunk in chunks {
+            send_message_with(Message::StreamChunk(chunk), capabilities, stream).await?;
+        }
+        if followed.is_empty() {
+            break;
+        }
+
+        changes
+            .changed()
+            .await
+            .map_err(|_| Error::Generic("Mock daemon state has been dropped".into()))?;
+    }
+
+    send_response(
+        create_success_message("All tasks finished."),
+        capabilities,
+        stream,
+    )
+    .await
+}
+
+/// Confirm the subscription and send all matching events, whenever the state changes. \
+/// The events are derived by comparing the state with its previous version.
+async fn subscribe(
+    subscription: SubscribeMessage,
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+    inner: &Mutex<MockState>,
+) -> Result<(), Error> {
+    let (mut previous, mut changes) = {
+        let inner = lock(inner);
+        (inner.state.clone(), inner.changes.subscribe())
+    };
+    send_response(
+        create_success_message("Subscribed to events."),
+        capabilities,
+        stream,
+    )
+    .await?;
+
+    loop {
+        changes
+            .changed()
+            .await
+            .map_err(|_| Error::Generic("Mock daemon state has been dropped".into()))?;
+        let current = lock(inner).state.clone();
+
+        for event in state_events(&previous, &current) {
+            if subscription.matches(&event) {
+                send_message_with(Message::Event(event), capabilities, stream).await?;
+            }
+        }
+        previous = current;
+    }
+}
+
+/// Get the events that lead from the previous to the current state.
+fn state_events(previous: &State, current: &State) -> Vec<Event> {
+    let mut events = Vec::new();
+    for (task_id, task) in current.tasks.iter() {
+        match previous.tasks.get(task_id) {
+            None => events.push(Event::TaskAdded(task.clone())),
+            Some(previous) if previous.status != task.status => {
+                events.push(Event::TaskStatusChanged {
+                    task_id: *task_id,
+                    group: task.group.clone(),
+                    previous_status: previous.status.clone(),
+                    status: task.status.clone(),
+                })
+            }
+            Some(_) => (),
+        }
+    }
+    for (task_id, task) in previous.tasks.iter() {
+        if !current.tasks.contains_key(task_id) {
+            events.push(Event::TaskRemoved {
+                task_id: *task_id,
+                group: task.group.clone(),
+            });
+        }
+    }
+
+    for (name, group) in current.groups.iter() {
+        if previous.groups.get(name) != Some(group) {
+            events.push(Event::GroupChanged {
+                name: name.clone(),
+                group: Some(group.clone()),
+            });
+        }
+    }
+    for name in previous.groups.keys() {
+        if !current.groups.contains_key(name) {
+            events.push(Event::GroupChanged {
+                name: name.clone(),
+                group: None,
+            });
+        }
+    }
+
+    events
+}
+
+/// Check that the selected group or all selected tasks exist.
+fn check_selection(selection: &TaskSelection, state: &State) -> Option<Message> {
+    match selection {
+        TaskSelection::Group(group) if !state.groups.contains_key(group) => {
+            Some(group_not_found(group))
+        }
+        TaskSelection::TaskIds(task_ids) => {
+            let missing: Vec<usize> = task_ids
+                .iter()
+                .filter(|id| !state.tasks.contains_key(id))
+                .cloned()
+                .collect();
+            if missing.is_empty() {
+                return None;
+            }
+
+            Some(create_structured_failure_message(
+                ErrorCode::TaskNotFound,
+                missing.clone(),
+                format!("Tasks don't exist: {}", join(&missing)),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Lock a queued or stashed task and send its details.
+fn edit_request(task_id: usize, state: &mut State) -> Message {
+    let task = match state.tasks.get_mut(&task_id) {
+        Some(task) => task,
+        None => {
+            return create_structured_failure_message(
+                ErrorCode::TaskNotFound,
+                vec![task_id],
+                format!("Task {} doesn't exist", task_id),
+            )
+        }
+    };
+    if !matches!(task.status, TaskStatus::Queued | TaskStatus::Stashed { .. }) {
+        return create_structured_failure_message(
+            ErrorCode::InvalidTaskStatus,
+            vec![task_id],
+            "You can only edit a queued/stashed task",
+        );
+    }
+
+    task.prev_status = task.status.clone();
+    task.status = TaskStatus::Locked;
+
+    Message::EditResponse(EditResponseMessage::from_task(task))
+}
+
+/// Apply the edit of a locked task and unlock it again.
+fn edit(message: EditMessage, capabilities: &Capabilities, state: &mut State) -> Message {
+    let task_id = message.task_id;
+    match state.tasks.get(&task_id) {
+        Some(task) if task.status == TaskStatus::Locked => (),
+        _ => {
+            return create_structured_failure_message(
+                ErrorCode::InvalidTaskStatus,
+                vec![task_id],
+                "Task is no longer locked",
+            )
+        }
+    }
+
+    // Unlock the task first, as locked tasks can't be changed.
+    let task = state.tasks.get_mut(&task_id).unwrap();
+    task.status = task.prev_status.clone();
+
+    let changes = message.into_changes(capabilities, &task.status);
+    match state.edit_task(task_id, changes) {
+        Ok(()) => create_success_message(format!("Task {} has been edited.", task_id)),
+        Err(err) => failure_response(err),
+    }
+}
+
+/// Kill the selected tasks right away and record how they have been killed.
+/// An explicit `signal` is only sent without an escalation.
+fn kill(message: KillMessage, inner: &mut MockState) -> Message {
+    let MockState { state, kills, .. } = inner;
+    if let TaskSelection::Group(group) = &message.tasks {
+        if !state.groups.contains_key(group) {
+            return group_not_found(group);
+        }
+    }
+
+    let mut succeeded = Vec::new();
+    let mut not_running = Vec::new();
+    let mut not_found = Vec::new();
+    if let TaskSelection::TaskIds(task_ids) = &message.tasks {
+        not_found = task_ids
+            .iter()
+            .filter(|id| !state.tasks.contains_key(id))
+            .cloned()
+            .collect();
+    }
+    for task_id in state.task_ids_in_selection(&message.tasks) {
+        let task = state.tasks.get_mut(&task_id).unwrap();
+        match task.status {
+            TaskStatus::Running | TaskStatus::Paused => {
+                let escalation = match (&message.signal, &message.escalation) {
+                    (Some(_), None) => None,
+                    _ => task
+                        .kill_escalation_or(message.escalation.as_ref())
+                        .cloned(),
+                };
+                kills.insert(
+                    task_id,
+                    MockKill {
+                        signal: match escalation {
+                            Some(_) => None,
+                            None => message.signal.clone(),
+                        },
+                        escalation,
+                        process_group: message.process_group,
+                        children: message.children,
+                    },
+                );
+                set_task_status(task, TaskStatus::Done(TaskResult::Killed));
+                succeeded.push(task_id);
+            }
+            _ => not_running.push(task_id),
+        }
+    }
+
+    let mut failures = Vec::new();
+    if !not_running.is_empty() {
+        failures.push(FailureMessage {
+            code: ErrorCode::TaskNotRunning,
+            text: format!("Tasks are not running: {}", join(&not_running)),
+            task_ids: not_running,
+        });
+    }
+    if !not_found.is_empty() {
+        failures.push(FailureMessage {
+            code: ErrorCode::TaskNotFound,
+            text: format!("Tasks don't exist: {}", join(&not_found)),
+            task_ids: not_found,
+        });
+    }
+
+    partial_response(succeeded, "Tasks are being killed", failures)
+}
+
+/// Build the response of a request that may only have been applied to some of the tasks.
+fn partial_response(
+    succeeded: Vec<usize>,
+    text: &str,
+    mut failures: Vec<FailureMessage>,
+) -> Message {
+    let mut lines = Vec::new();
+    if !succeeded.is_empty() {
+        lines.push(format!("{}: {}", text, join(&succeeded)));
+    }
+    if failures.is_empty() {
+        return create_success_message(lines.join("\n"));
+    }
+    if succeeded.is_empty() && failures.len() == 1 {
+        return Message::StructuredFailure(failures.remove(0));
+    }
+    lines.extend(failures.iter().map(|failure| failure.text.clone()));
+
+    Message::PartialSuccess(PartialSuccessMessage {
+        succeeded,
+        failures,
+        text: lines.join("\n"),
+    })
+}
+
+fn move_to_group(message: MoveToGroupMessage, state: &mut State) -> Message {
+    let (moved, not_moved) = match state.move_tasks_to_group(message.task_ids, &message.group) {
+        Ok(result) => result,
+        Err(err) => return failure_response(err),
+    };
+    let (not_found, running): (Vec<usize>, Vec<usize>) = not_moved
+        .into_iter()
+        .partition(|task_id| !state.tasks.contains_key(task_id));
+
+    let mut failures = Vec::new();
+    if !running.is_empty() {
+        failures.push(FailureMessage {
+            code: ErrorCode::TaskRunning,
+            text: format!("Tasks are running: {}", join(&running)),
+            task_ids: running,
+        });
+    }
+    if !not_found.is_empty() {
+        failures.push(FailureMessage {
+            code: ErrorCode::TaskNotFound,
+            text: format!("Tasks don't exist: {}", join(&not_found)),
+            task_ids: not_found,
+        });
+    }
+
+    partial_response(
+        moved,
+        &format!("Tasks have been moved to group \"{}\"", message.group),
+        failures,
+    )
+}
+
+fn env(message: EnvMessage, state: &mut State) -> Message {
+    let tasks = match &message {
+        EnvMessage::Set { tasks, .. } | EnvMessage::Unset { tasks, .. } => tasks,
+        EnvMessage::List(tasks) => tasks,
+    };
+    if let Some(failure) = check_selection(tasks, state) {
+        return failure;
+    }
+    let task_ids = state.task_ids_in_selection(tasks);
+
+    let (changed, running) = match &message {
+        EnvMessage::Set { key, value, .. } => state.set_task_env(task_ids, key, value),
+        EnvMessage::Unset { key, .. } => state.unset_task_env(task_ids, key),
+        EnvMessage::List(_) => {
+            return Message::EnvResponse(EnvResponseMessage {
+                envs: state.task_envs(&task_ids),
+            })
+        }
+    };
+
+    let mut failures = Vec::new();
+    if !running.is_empty() {
+        failures.push(FailureMessage {
+            code: ErrorCode::TaskRunning,
+            text: format!("Tasks are running: {}", join(&running)),
+            task_ids: running,
+        });
+    }
+
+    partial_response(changed, "Environment has been changed", failures)
+}
+
+fn group(message: GroupMessage, state: &mut State) -> Message {
+    match message {
+        GroupMessage::Add {
+            name,
+            parallel_tasks,
+            time_windows,
+            ordering,
+            parent,
+        } => {
+            if state.groups.contains_key(&name) {
+                return create_structured_failure_message(
+                    ErrorCode::GroupExists,
+                    Vec::new(),
+                    format!("Group \"{}\" already exists", name),
+                );
+            }
+            if let Some(parent) = &parent {
+                if !state.groups.contains_key(parent) {
+                    return group_not_found(parent);
+                }
+            }
+
+            let group = state.create_group(&name);
+            if let Some(parallel_tasks) = parallel_tasks {
+                group.parallel_tasks = parallel_tasks;
+            }
+            group.time_windows = time_windows.unwrap_or_default();
+            group.ordering = ordering.unwrap_or_default();
+            group.parent = parent;
+
+            create_success_message(format!("New group \"{}\" has been added.", name))
+        }
+        GroupMessage::Edit {
+            name,
+            time_windows,
+            ordering,
+        } => {
+            let group = match state.groups.get_mut(&name) {
+                Some(group) => group,
+                None => return group_not_found(&name),
+            };
+            if let Some(time_windows) = time_windows {
+                group.time_windows = time_windows;
+            }
+            if let Some(ordering) = ordering {
+                group.ordering = ordering;
+            }
+
+            create_success_message(format!("Group \"{}\" has been changed.", name))
+        }
+        GroupMessage::SetParent { name, parent } => {
+            match state.set_group_parent(&name, parent.as_deref()) {
+                Ok(()) => create_success_message(format!("Parent of \"{}\" has been set.", name)),
+                Err(err) => failure_response(err),
+            }
+        }
+        GroupMessage::Rename { name, new_name } => match state.rename_group(&name, &new_name) {
+            Ok(()) => create_success_message(format!(
+                "Group \"{}\" has been renamed to \"{}\".",
+                name, new_name
+            )),
+            Err(err) => failure_response(err),
+        },
+        GroupMessage::Remove(name) => {
+            if !state.groups.contains_key(&name) {
+                return group_not_found(&name);
+            }
+            match state.remove_group(&name) {
+                Ok(()) => create_success_message(format!("Group \"{}\" has been removed", name)),
+                Err(err) => invalid_request(err),
+            }
+        }
+        GroupMessage::List => Message::GroupResponse(GroupResponseMessage {
+            groups: state.groups.clone(),
+        }),
+    }
+}
+
+/// Collect the requested tasks and their [snap] compressed output.
+fn get_logs(
+    request: LogRequestMessage,
+    inner: &MockState,
+) -> Result<BTreeMap<usize, TaskLogMessage>, Error> {
+    let mut logs = BTreeMap::new();
+    for (task_id, task) in inner.state.tasks.iter() {
+        if !request.task_ids.is_empty() && !request.task_ids.contains(task_id) {
+            continue;
+        }
+
+        let (mut stdout, mut stderr) = (None, None);
+        if request.send_logs {
+            let (out, err) = inner.outputs.get(task_id).cloned().unwrap_or_default();
+            stdout = Some(compress_output(&out, request.lines)?);
+            stderr = Some(compress_output(&err, request.lines)?);
+        }
+        logs.insert(
+            *task_id,
+            TaskLogMessage {
+                task: task.clone(),
+                stdout,
+                stderr,
+            },
+        );
+    }
+
+    Ok(logs)
+}
+
+/// Compress the output like the daemon does. `lines` only keeps the last lines.
+fn compress_output(output: &str, lines: Option<usize>) -> Result<Vec<u8>, Error> {
+    let output = match lines {
+        Some(lines) => {
+            let all: Vec<&str> = output.lines().collect();
+            let mut last = all[all.len().saturating_sub(lines)..].join("\n");
+            if !last.is_empty() && output.ends_with('\n') {
+                last.push('\n');
+            }
+            last
+        }
+        None => output.to_string(),
+    };
+
+    let mut encoder = FrameEncoder::new(Vec::new());
+    encoder.write_all(output.as_bytes())?;
+
+    encoder
+        .into_inner()
+        .map_err(|err| Error::Generic(format!("Failed to compress output: {}", err)))
+}
+
+/// Change the status of a task and set its start and end times accordingly.
+fn set_task_status(task: &mut Task, status: TaskStatus) {
+    match status {
+        TaskStatus::Running if task.start.is_none() => task.start = Some(Local::now()),
+        TaskStatus::Done(_) => {
+            if task.start.is_none() {
+                task.start = Some(Local::now());
+            }
+            task.end = Some(Local::now());
+        }
+        _ => (),
+    }
+    task.prev_status = task.status.clone();
+    task.status = status;
+}
+
+fn join(task_ids: &[usize]) -> String {
+    task_ids
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn task_not_found(task_id: usize) -> Error {
+    Error::Failure(FailureMessage {
+        code: ErrorCode::TaskNotFound,
+        task_ids: vec![task_id],
+        text: format!("Task {} doesn't exist", task_id),
+    })
+}
+
+fn group_not_found(group: &str) -> Message {
+    create_structured_failure_message(
+        ErrorCode::GroupNotFound,
+        Vec::new(),
+        format!("Group \"{}\" doesn't exist", group),
+    )
+}
+
+/// Send [Error::Failure]s as they are, all other errors as invalid request.
+fn failure_response(err: Error) -> Message {
+    match err {
+        Error::Failure(failure) => Message::StructuredFailure(failure),
+        err => invalid_request(err),
+    }
+}
+
+fn invalid_request(err: Error) -> Message {
+    create_structured_failure_message(ErrorCode::InvalidRequest, Vec::new(), err)
+}
@@ -1,14 +1,39 @@
+/// A blocking network layer and client, which doesn't need an async runtime.
+/// It speaks the same protocol, but only the client side is provided.
+#[cfg(feature = "blocking")]
+pub mod blocking;
 /// Used by the daemon to initialize the TLS certificats.
 pub mod certificate;
+/// A high-level client with typed requests to the daemon.
+#[cfg(feature = "async")]
+pub mod client;
+/// Compression of whole message frames.
+mod compression;
+/// A client-side dispatcher, which allows multiple requests to be in flight on one connection.
+#[cfg(feature = "async")]
+pub mod dispatcher;
+/// A local HTTP gateway, which exposes the daemon's API as REST/JSON.
+#[cfg(feature = "gateway")]
+pub mod gateway;
 /// This contains the main [Message](message::Message) enum and all its structs used to
 /// communicate with the daemon or client.
 pub mod message;
+/// An in-process mock daemon with an in-memory state for integration tests.
+#[cfg(all(feature = "mock", not(target_os = "windows")))]
+pub mod mock;
 /// Platform specific code regarding sockets
+#[cfg(feature = "async")]
 mod platform;
 /// This is a higher-level abstraction layer used for simple communication
 /// This is probably the most interesting part for you.
+///
+/// The functions that actually send and receive messages need the `async` feature.
 pub mod protocol;
+/// The requests of the async and blocking clients and the interpretation of their responses.
+#[cfg(any(feature = "async", feature = "blocking"))]
+mod requests;
 /// Functions to write and read the secret to/from a file.
 pub mod secret;
 /// Helper functions for reading and handling TLS files.
+#[cfg(any(feature = "async", feature = "blocking"))]
 mod tls;
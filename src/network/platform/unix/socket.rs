@@ -15,7 +15,7 @@ use crate::settings::Shared;
 pub fn socket_cleanup(settings: &Shared) -> Result<(), std::io::Error> {
     // Clean up the unix socket if we're using it and it exists.
     if settings.use_unix_socket && PathBuf::from(&settings.unix_socket_path()).exists() {
-        std::fs::remove_file(&settings.unix_socket_path())?;
+        std::fs::remove_file(settings.unix_socket_path())?;
     }
 
     Ok(())
@@ -118,10 +118,10 @@ pub async fn get_listener(settings: &Shared) -> Result<GenericListener, Error> {
                 return Err(Error::UnixSocketExists);
             }
 
-            std::fs::remove_file(&settings.unix_socket_path())?;
+            std::fs::remove_file(settings.unix_socket_path())?;
         }
 
-        return Ok(Box::new(UnixListener::bind(&settings.unix_socket_path())?));
+        return Ok(Box::new(UnixListener::bind(settings.unix_socket_path())?));
     }
 
     // This is the listener, which accepts low-level TCP connections
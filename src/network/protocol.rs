@@ -1,44 +1,503 @@
-use std::io::Cursor;
+#[cfg(any(feature = "async", feature = "blocking"))]
+use std::collections::BTreeMap;
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(any(feature = "async", feature = "blocking"))]
+use byteorder::{BigEndian, ByteOrder};
+#[cfg(any(feature = "async", feature = "blocking"))]
 use log::debug;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_cbor::de::from_slice;
 use serde_cbor::ser::to_vec;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 use crate::error::Error;
+#[cfg(any(feature = "async", feature = "blocking"))]
+use crate::network::compression::compress;
+#[cfg(feature = "async")]
+use crate::network::compression::decompress;
 use crate::network::message::*;
 
+pub use crate::network::compression::Compression;
+
 // Reexport all stream/socket related stuff for convenience purposes
+#[cfg(feature = "async")]
 pub use super::platform::socket::Stream;
+#[cfg(feature = "async")]
 pub use super::platform::socket::*;
 
+/// The newest protocol version spoken by this library.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The oldest protocol version this library is still compatible with.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Moving tasks between groups via [Message::MoveToGroup].
+pub const FEATURE_MOVE_TO_GROUP: &str = "move_to_group";
+/// Editing, renaming and nesting groups via [GroupMessage].
+pub const FEATURE_GROUP_EDIT: &str = "group_edit";
+/// The archive of cleaned tasks.
+pub const FEATURE_ARCHIVE: &str = "archive";
+
+/// Sending messages wrapped in an [Envelope], which allows multiple requests per connection.
+pub const FEATURE_MULTIPLEXING: &str = "multiplexing";
+
+/// Subscribing to daemon events via [Message::Subscribe].
+pub const FEATURE_EVENTS: &str = "events";
+
+/// Sending multiple requests at once via [Message::Batch].
+pub const FEATURE_BATCH: &str = "batch";
+
+/// Sending large log responses as a sequence of [Message::LogChunk]s.
+pub const FEATURE_LOG_CHUNKS: &str = "log_chunks";
+
+/// Compressing message frames with snap.
+pub const FEATURE_COMPRESSION_SNAP: &str = "compression_snap";
+/// Compressing message frames with zstd.
+pub const FEATURE_COMPRESSION_ZSTD: &str = "compression_zstd";
+
+/// Structured failures with an [ErrorCode] via [Message::StructuredFailure] and
+/// [Message::PartialSuccess].
+pub const FEATURE_ERROR_CODES: &str = "error_codes";
+
+/// Waiting for tasks on the daemon's side via [Message::Wait].
+pub const FEATURE_WAIT: &str = "wait";
+
+/// Following the raw output of several tasks via [Message::MultiStreamRequest].
+pub const FEATURE_MULTI_STREAM: &str = "multi_stream";
+
+/// Editing all properties of a task via [EditMessage] and [Message::PartialEdit].
+pub const FEATURE_TASK_EDIT: &str = "task_edit";
+
+/// Manipulating the environment variables of tasks via [EnvMessage].
+pub const FEATURE_ENV: &str = "env";
+
+/// All POSIX signals and signals to process groups via [KillMessage].
+pub const FEATURE_SIGNALS: &str = "signals";
+
+/// Killing tasks with a [KillEscalation] and a default escalation per task.
+pub const FEATURE_KILL_ESCALATION: &str = "kill_escalation";
+
+/// All optional features supported by this library.
+pub const SUPPORTED_FEATURES: &[&str] = &[
+    FEATURE_MOVE_TO_GROUP,
+    FEATURE_GROUP_EDIT,
+    FEATURE_ARCHIVE,
+    FEATURE_MULTIPLEXING,
+    FEATURE_EVENTS,
+    FEATURE_BATCH,
+    FEATURE_LOG_CHUNKS,
+    FEATURE_COMPRESSION_SNAP,
+    FEATURE_COMPRESSION_ZSTD,
+    FEATURE_ERROR_CODES,
+    FEATURE_WAIT,
+    FEATURE_MULTI_STREAM,
+    FEATURE_TASK_EDIT,
+    FEATURE_ENV,
+    FEATURE_SIGNALS,
+    FEATURE_KILL_ESCALATION,
+];
+
+/// The highest byte of a frame header contains the frame's flags.
+/// The remaining bytes contain the size of the payload.
+#[cfg(any(feature = "async", feature = "blocking"))]
+const FRAME_SIZE_MASK: u64 = 0x00FF_FFFF_FFFF_FFFF;
+
+/// The default for the maximum size of a single message frame (64 MiB).
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+/// The maximum amount of log output per [Message::LogChunk] (1 MiB).
+pub const LOG_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The payload buffer of a received frame initially has at most this size (64 KiB). \
+/// It only grows as data actually arrives, so a forged header can't make us allocate
+/// [DEFAULT_MAX_FRAME_SIZE] bytes up front.
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub(crate) const INITIAL_PAYLOAD_CAPACITY: usize = 64 * 1024;
+
+/// The encoding of all messages on a connection.
+///
+/// The format is chosen by the client, simply by encoding its [Handshake] in that format.
+/// The daemon detects the format of the handshake and uses it for the rest of the connection.
+/// The framing, i.e. the size header and the optional compression, is the same for all formats.
+///
+/// [WireFormat::Json] exists for clients that are written in other languages.
+/// Its representation is stable and follows serde's default JSON representation:
+///
+/// - Messages are externally tagged enums. Variants without content are plain strings, such as
+///   `"Status"`. All other variants are objects with a single key, the name of the variant,
+///   such as `{"Remove": [0, 1]}` or `{"Switch": {"task_id_1": 0, "task_id_2": 1}}`.
+/// - Optional fields are `null` if they aren't set.
+/// - Points in time are RFC 3339 strings.
+/// - Byte arrays, such as compressed log output, are arrays of numbers.
+/// - Maps with integer keys, such as the tasks in a log response, use strings as keys.
+/// - [Message::StatusResponse] contains the state in the same representation that's used by the
+///   daemon's state file.
+///
+/// An example of every message can be found in the `tests/data/json_messages.json` file of this
+/// repository.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum WireFormat {
+    #[default]
+    Cbor,
+    Json,
+}
+
+#[cfg(any(feature = "async", feature = "blocking"))]
+impl WireFormat {
+    /// Detect the format of a serialized handshake. \
+    /// A CBOR encoded handshake is a map, which never starts with an opening brace.
+    fn detect(payload: &[u8]) -> WireFormat {
+        match payload.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') => WireFormat::Json,
+            _ => WireFormat::Cbor,
+        }
+    }
+}
+
+/// Parse the handshake of the other side and detect the format it has been encoded in.
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub(crate) fn parse_handshake(payload: &[u8]) -> Result<(Handshake, WireFormat), Error> {
+    let format = WireFormat::detect(payload);
+
+    // Peers that don't know about the handshake send something else entirely.
+    let handshake = deserialize(payload, format).map_err(|_| Error::MissingHandshake {
+        remote_version: None,
+    })?;
+
+    Ok((handshake, format))
+}
+
+/// Determine the client's capabilities from the daemon's answer to its handshake.
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub(crate) fn finish_client_handshake(
+    local: &Handshake,
+    answer: Result<Vec<u8>, Error>,
+    format: WireFormat,
+) -> Result<Capabilities, Error> {
+    // Daemons without a handshake close the connection, as they don't understand it.
+    let payload = answer.map_err(|_| Error::MissingHandshake {
+        remote_version: None,
+    })?;
+    let (remote, _) = parse_handshake(&payload)?;
+
+    let mut capabilities = negotiate(local, &remote)?;
+    capabilities.format = format;
+
+    Ok(capabilities)
+}
+
+/// Add the version, which the daemon sent before the handshake, to a [Error::MissingHandshake].
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub(crate) fn with_remote_version(err: Error, version: &str) -> Error {
+    match err {
+        Error::MissingHandshake { .. } => Error::MissingHandshake {
+            remote_version: Some(version.to_string()),
+        },
+        err => err,
+    }
+}
+
+/// Serialize a message, which is about to be sent, in the given wire format.
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub(crate) fn encode_message(message: &Message, format: WireFormat) -> Result<Vec<u8>, Error> {
+    debug!("Sending message: {:?}", message);
+
+    serialize(message, format)
+}
+
+/// Deserialize a received message from the given wire format.
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub(crate) fn decode_message(payload: &[u8], format: WireFormat) -> Result<Message, Error> {
+    if payload.is_empty() {
+        return Err(Error::EmptyPayload);
+    }
+
+    let message: Message = deserialize(payload, format)?;
+    debug!("Received message: {:?}", message);
+
+    Ok(message)
+}
+
+/// Collects the logs of a single [Message::LogResponse] or a sequence of [Message::LogChunk]s.
+#[cfg(any(feature = "async", feature = "blocking"))]
+#[derive(Default)]
+pub(crate) struct LogCollector {
+    logs: BTreeMap<usize, TaskLogMessage>,
+}
+
+#[cfg(any(feature = "async", feature = "blocking"))]
+impl LogCollector {
+    /// Add the next received message. Returns the logs, once the response is complete. \
+    /// Failures and any other message are returned as an error.
+    pub(crate) fn add(
+        &mut self,
+        message: Message,
+    ) -> Result<Option<BTreeMap<usize, TaskLogMessage>>, Error> {
+        match message.into_result()? {
+            Message::LogResponse(logs) => Ok(Some(logs)),
+            Message::LogChunk(chunk) => {
+                let last = chunk.last;
+                merge_log_chunk(&mut self.logs, chunk)?;
+
+                Ok(last.then(|| std::mem::take(&mut self.logs)))
+            }
+            message => Err(Error::UnexpectedResponse(format!(
+                "Expected log response, got {:?}",
+                message
+            ))),
+        }
+    }
+}
+
+/// Serialize a value in the given wire format.
+pub fn serialize<T: Serialize>(value: &T, format: WireFormat) -> Result<Vec<u8>, Error> {
+    let result = match format {
+        WireFormat::Cbor => to_vec(value).map_err(|err| err.to_string()),
+        WireFormat::Json => serde_json::to_vec(value).map_err(|err| err.to_string()),
+    };
+
+    result.map_err(Error::MessageSerialization)
+}
+
+/// Deserialize a value from the given wire format.
+pub fn deserialize<T: DeserializeOwned>(payload: &[u8], format: WireFormat) -> Result<T, Error> {
+    let result = match format {
+        WireFormat::Cbor => from_slice(payload).map_err(|err| err.to_string()),
+        WireFormat::Json => serde_json::from_slice(payload).map_err(|err| err.to_string()),
+    };
+
+    result.map_err(Error::MessageDeserialization)
+}
+
+/// The result of a successful handshake.
+/// This contains the protocol version and features that are supported by both sides.
+///
+/// `compression` is the best frame compression that's supported by both sides.
+/// `format` is the encoding of all messages, which has been chosen by the client.
+/// `max_frame_size` is the maximum size of a single frame on this connection. It isn't
+/// negotiated and defaults to [DEFAULT_MAX_FRAME_SIZE]. Frames that exceed it are neither sent
+/// nor received.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Capabilities {
+    pub version: u32,
+    pub features: Vec<String>,
+    pub compression: Compression,
+    pub format: WireFormat,
+    pub max_frame_size: usize,
+}
+
+impl Capabilities {
+    /// Check whether both sides support a feature.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|supported| supported == feature)
+    }
+
+    /// Check whether the other side is able to understand a message. \
+    /// This should be used before sending any messages that have been added to the protocol
+    /// later on.
+    pub fn check_message(&self, message: &Message) -> Result<(), Error> {
+        match message
+            .required_features()
+            .into_iter()
+            .find(|feature| !self.supports(feature))
+        {
+            Some(feature) => Err(Error::UnsupportedFeature(feature.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Create the handshake that describes this library's protocol.
+pub fn local_handshake() -> Handshake {
+    Handshake {
+        version: PROTOCOL_VERSION,
+        min_version: MIN_PROTOCOL_VERSION,
+        features: SUPPORTED_FEATURES
+            .iter()
+            .map(|feature| feature.to_string())
+            .collect(),
+    }
+}
+
+/// Determine the capabilities of a connection from both sides' handshakes. \
+/// The highest protocol version that's spoken by both sides is used.
+pub fn negotiate(local: &Handshake, remote: &Handshake) -> Result<Capabilities, Error> {
+    let version = local.version.min(remote.version);
+    if version < local.min_version.max(remote.min_version) {
+        return Err(Error::IncompatibleProtocol {
+            local: local.version,
+            local_min: local.min_version,
+            remote: remote.version,
+            remote_min: remote.min_version,
+        });
+    }
+
+    let features: Vec<String> = local
+        .features
+        .iter()
+        .filter(|feature| remote.features.contains(feature))
+        .cloned()
+        .collect();
+
+    let compression = if features
+        .iter()
+        .any(|feature| feature == FEATURE_COMPRESSION_ZSTD)
+    {
+        Compression::Zstd
+    } else if features
+        .iter()
+        .any(|feature| feature == FEATURE_COMPRESSION_SNAP)
+    {
+        Compression::Snap
+    } else {
+        Compression::None
+    };
+
+    Ok(Capabilities {
+        version,
+        features,
+        compression,
+        format: WireFormat::Cbor,
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+    })
+}
+
+/// The client side of the handshake. \
+/// The client sends its handshake first and then waits for the daemon's handshake.
+#[cfg(feature = "async")]
+pub async fn client_handshake(stream: &mut GenericStream) -> Result<Capabilities, Error> {
+    client_handshake_with_format(WireFormat::Cbor, stream).await
+}
+
+/// Same as [client_handshake], but all messages on this connection use the given format.
+#[cfg(feature = "async")]
+pub async fn client_handshake_with_format(
+    format: WireFormat,
+    stream: &mut GenericStream,
+) -> Result<Capabilities, Error> {
+    let local = local_handshake();
+    send_handshake(&local, format, stream).await?;
+    let answer = receive_bytes(stream).await;
+
+    finish_client_handshake(&local, answer, format)
+}
+
+/// The daemon side of the handshake. \
+/// The daemon waits for the client's handshake and answers with its own handshake.
+/// The daemon always answers, so the client is able to report incompatible versions.
+/// The daemon answers in the same [WireFormat] as the client's handshake.
+#[cfg(feature = "async")]
+pub async fn daemon_handshake(stream: &mut GenericStream) -> Result<Capabilities, Error> {
+    let (remote, format) = receive_handshake(stream).await?;
+    let local = local_handshake();
+    send_handshake(&local, format, stream).await?;
+
+    let mut capabilities = negotiate(&local, &remote)?;
+    capabilities.format = format;
+
+    Ok(capabilities)
+}
+
+#[cfg(feature = "async")]
+async fn send_handshake(
+    handshake: &Handshake,
+    format: WireFormat,
+    stream: &mut GenericStream,
+) -> Result<(), Error> {
+    let payload = serialize(handshake, format)?;
+
+    send_bytes(&payload, stream).await
+}
+
+#[cfg(feature = "async")]
+async fn receive_handshake(stream: &mut GenericStream) -> Result<(Handshake, WireFormat), Error> {
+    let payload = receive_bytes(stream).await?;
+
+    parse_handshake(&payload)
+}
+
 /// Convenience wrapper around send_bytes.
 /// Deserialize a message and feed the bytes into send_bytes.
+#[cfg(feature = "async")]
 pub async fn send_message(message: Message, stream: &mut GenericStream) -> Result<(), Error> {
-    debug!("Sending message: {:?}", message);
-    // Prepare command for transfer and determine message byte size
-    let payload = to_vec(&message).map_err(|err| Error::MessageDeserialization(err.to_string()))?;
+    let payload = encode_message(&message, WireFormat::Cbor)?;
 
     send_bytes(&payload, stream).await
 }
 
+/// Same as [send_message], but the message is compressed with the given compression. \
+/// Only use a compression that has been negotiated during the handshake.
+#[cfg(feature = "async")]
+pub async fn send_message_compressed(
+    message: Message,
+    compression: Compression,
+    stream: &mut GenericStream,
+) -> Result<(), Error> {
+    let payload = encode_message(&message, WireFormat::Cbor)?;
+
+    send_bytes_compressed(&payload, compression, stream).await
+}
+
+/// Same as [send_message], but the message is encoded in the format and compressed with the
+/// compression that have been negotiated during the handshake.
+#[cfg(feature = "async")]
+pub async fn send_message_with(
+    message: Message,
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+) -> Result<(), Error> {
+    let payload = encode_message(&message, capabilities.format)?;
+
+    write_bytes(
+        &payload,
+        capabilities.compression,
+        capabilities.max_frame_size,
+        stream,
+    )
+    .await
+}
+
 /// Send a Vec of bytes. Before the actual bytes are send, the size of the message
 /// is transmitted in an header of fixed size (u64).
+#[cfg(feature = "async")]
 pub async fn send_bytes(payload: &[u8], stream: &mut GenericStream) -> Result<(), Error> {
-    let message_size = payload.len() as u64;
+    write_bytes(payload, Compression::None, DEFAULT_MAX_FRAME_SIZE, stream).await
+}
+
+/// Same as [send_bytes], but the payload is compressed with the given compression. \
+/// Small payloads or payloads that don't get any smaller are sent uncompressed.
+/// The used compression is flagged in the header.
+#[cfg(feature = "async")]
+pub async fn send_bytes_compressed(
+    payload: &[u8],
+    compression: Compression,
+    stream: &mut GenericStream,
+) -> Result<(), Error> {
+    write_bytes(payload, compression, DEFAULT_MAX_FRAME_SIZE, stream).await
+}
 
-    let mut header = vec![];
-    WriteBytesExt::write_u64::<BigEndian>(&mut header, message_size).unwrap();
+/// The implementation of [send_bytes], which works on anything that can be written to.
+/// This allows to use it on the write half of a split stream.
+#[cfg(feature = "async")]
+pub(crate) async fn write_bytes<W>(
+    payload: &[u8],
+    compression: Compression,
+    max_frame_size: usize,
+    writer: &mut W,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let (header, payload) = encode_frame(payload, compression, max_frame_size)?;
 
     // Send the request size header first.
     // Afterwards send the request.
-    stream.write_all(&header).await?;
+    writer.write_all(&header).await?;
 
     // Split the payload into 1.4Kbyte chunks
     // 1.5Kbyte is the MUT for TCP, but some carrier have a little less, such as Wireguard.
     for chunk in payload.chunks(1400) {
-        stream.write_all(chunk).await?;
+        writer.write_all(chunk).await?;
     }
 
     Ok(())
@@ -48,24 +507,40 @@ pub async fn send_bytes(payload: &[u8], stream: &mut GenericStream) -> Result<()
 /// This is the basic protocol beneath all pueue communication. \
 ///
 /// 1. The client sends a u64, which specifies the length of the payload.
+///    The highest byte contains flags, such as the compression of the payload.
 /// 2. Receive chunks of 1400 bytes until we finished all expected bytes
+/// 3. Decompress the payload, if it's compressed.
+#[cfg(feature = "async")]
 pub async fn receive_bytes(stream: &mut GenericStream) -> Result<Vec<u8>, Error> {
+    read_bytes(stream, DEFAULT_MAX_FRAME_SIZE).await
+}
+
+/// The implementation of [receive_bytes], which works on anything that can be read from.
+/// This allows to use it on the read half of a split stream.
+#[cfg(feature = "async")]
+pub(crate) async fn read_bytes<R>(reader: &mut R, max_frame_size: usize) -> Result<Vec<u8>, Error>
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
     // Receive the header with the overall message size
-    let mut header = vec![0; 8];
-    stream.read_exact(&mut header).await?;
-    let mut header = Cursor::new(header);
-    let message_size = ReadBytesExt::read_u64::<BigEndian>(&mut header)? as usize;
+    let mut header = [0; 8];
+    reader.read_exact(&mut header).await?;
+    let (message_size, compression) = decode_header(header, max_frame_size)?;
 
-    // Buffer for the whole payload
-    let mut payload_bytes = Vec::with_capacity(message_size);
+    // Buffer for the whole payload, which grows while the payload arrives.
+    let mut payload_bytes = Vec::with_capacity(message_size.min(INITIAL_PAYLOAD_CAPACITY));
 
     // Create a static buffer with our packet size.
     let mut chunk_buffer: [u8; 1400] = [0; 1400];
 
     // Receive chunks until we reached the expected message size
     while payload_bytes.len() < message_size {
+        // Never read more than the remaining payload.
+        // Otherwise we would swallow the beginning of the next message.
+        let remaining = (message_size - payload_bytes.len()).min(chunk_buffer.len());
+
         // Read data and get the amount of received bytes
-        let received_bytes = stream.read(&mut chunk_buffer).await?;
+        let received_bytes = reader.read(&mut chunk_buffer[0..remaining]).await?;
 
         if received_bytes == 0 {
             return Err(Error::Connection(
@@ -78,26 +553,190 @@ pub async fn receive_bytes(stream: &mut GenericStream) -> Result<Vec<u8>, Error>
         payload_bytes.extend_from_slice(&chunk_buffer[0..received_bytes]);
     }
 
-    Ok(payload_bytes)
+    decompress(payload_bytes, compression, max_frame_size)
+}
+
+/// Compress the payload, if it's worth it, and create the header of the frame.
+/// The header contains the payload size and the compression flag in its highest byte.
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub(crate) fn encode_frame(
+    payload: &[u8],
+    compression: Compression,
+    max_frame_size: usize,
+) -> Result<([u8; 8], Vec<u8>), Error> {
+    check_frame_size(payload.len(), max_frame_size)?;
+
+    let (payload, compression) = match compress(payload, compression)? {
+        Some(compressed) => (compressed, compression),
+        None => (payload.to_vec(), Compression::None),
+    };
+    let message_size = payload.len() as u64;
+
+    let mut header = [0; 8];
+    let flags = u64::from(compression.flag()) << 56;
+    BigEndian::write_u64(&mut header, flags | message_size);
+
+    Ok((header, payload))
+}
+
+/// Get the payload size and compression of a frame from its header.
+#[cfg(any(feature = "async", feature = "blocking"))]
+pub(crate) fn decode_header(
+    header: [u8; 8],
+    max_frame_size: usize,
+) -> Result<(usize, Compression), Error> {
+    let header = BigEndian::read_u64(&header);
+
+    // Don't trust the header, before we allocate any memory.
+    let message_size = usize::try_from(header & FRAME_SIZE_MASK).unwrap_or(usize::MAX);
+    check_frame_size(message_size, max_frame_size)?;
+    let compression = Compression::from_flag((header >> 56) as u8)?;
+
+    Ok((message_size, compression))
+}
+
+#[cfg(any(feature = "async", feature = "blocking"))]
+fn check_frame_size(size: usize, max: usize) -> Result<(), Error> {
+    if size > max {
+        return Err(Error::FrameTooLarge { size, max });
+    }
+
+    Ok(())
+}
+
+/// Send the logs of tasks. \
+/// If the other side supports [FEATURE_LOG_CHUNKS], logs that are larger than a single
+/// [LOG_CHUNK_SIZE] are sent as a sequence of [Message::LogChunk]s instead of a single
+/// [Message::LogResponse].
+#[cfg(feature = "async")]
+pub async fn send_log_response(
+    logs: BTreeMap<usize, TaskLogMessage>,
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+) -> Result<(), Error> {
+    let log_size: usize = logs
+        .values()
+        .map(|log| {
+            log.stdout.as_ref().map_or(0, Vec::len) + log.stderr.as_ref().map_or(0, Vec::len)
+        })
+        .sum();
+
+    if log_size <= LOG_CHUNK_SIZE || !capabilities.supports(FEATURE_LOG_CHUNKS) {
+        return send_message_with(Message::LogResponse(logs), capabilities, stream).await;
+    }
+
+    for chunk in split_log_response(logs, LOG_CHUNK_SIZE) {
+        send_message_with(Message::LogChunk(chunk), capabilities, stream).await?;
+    }
+
+    Ok(())
+}
+
+/// Receive the logs of tasks. \
+/// This handles both, a single [Message::LogResponse] and a sequence of [Message::LogChunk]s.
+/// Any other message is returned as an error.
+#[cfg(feature = "async")]
+pub async fn receive_log_response(
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+) -> Result<BTreeMap<usize, TaskLogMessage>, Error> {
+    let mut collector = LogCollector::default();
+    loop {
+        let message = receive_message_with(capabilities, stream).await?;
+        if let Some(logs) = collector.add(message)? {
+            return Ok(logs);
+        }
+    }
 }
 
 /// Convenience wrapper that receives a message and converts it into a Message.
+#[cfg(feature = "async")]
 pub async fn receive_message(stream: &mut GenericStream) -> Result<Message, Error> {
     let payload_bytes = receive_bytes(stream).await?;
     debug!("Received {} bytes", payload_bytes.len());
+
+    decode_message(&payload_bytes, WireFormat::Cbor)
+}
+
+/// Same as [receive_message], but the message is decoded from the format that has been
+/// negotiated during the handshake.
+#[cfg(feature = "async")]
+pub async fn receive_message_with(
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+) -> Result<Message, Error> {
+    let payload_bytes = read_bytes(stream, capabilities.max_frame_size).await?;
+
+    decode_message(&payload_bytes, capabilities.format)
+}
+
+/// Send a message wrapped in an [Envelope] with the given request id. \
+/// The daemon uses this to answer requests on multiplexed connections.
+#[cfg(feature = "async")]
+pub async fn send_envelope(
+    request_id: u64,
+    message: Message,
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+) -> Result<(), Error> {
+    let envelope = Envelope {
+        request_id,
+        message,
+    };
+
+    write_envelope(&envelope, capabilities, stream).await
+}
+
+/// Receive a message that's wrapped in an [Envelope].
+#[cfg(feature = "async")]
+pub async fn receive_envelope(
+    capabilities: &Capabilities,
+    stream: &mut GenericStream,
+) -> Result<Envelope, Error> {
+    read_envelope(capabilities, stream).await
+}
+
+#[cfg(feature = "async")]
+pub(crate) async fn write_envelope<W>(
+    envelope: &Envelope,
+    capabilities: &Capabilities,
+    writer: &mut W,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    debug!("Sending envelope: {:?}", envelope);
+    let payload = serialize(envelope, capabilities.format)?;
+
+    write_bytes(
+        &payload,
+        capabilities.compression,
+        capabilities.max_frame_size,
+        writer,
+    )
+    .await
+}
+
+#[cfg(feature = "async")]
+pub(crate) async fn read_envelope<R>(
+    capabilities: &Capabilities,
+    reader: &mut R,
+) -> Result<Envelope, Error>
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let payload_bytes = read_bytes(reader, capabilities.max_frame_size).await?;
     if payload_bytes.is_empty() {
         return Err(Error::EmptyPayload);
     }
 
-    // Deserialize the message.
-    let message: Message =
-        from_slice(&payload_bytes).map_err(|err| Error::MessageDeserialization(err.to_string()))?;
-    debug!("Received message: {:?}", message);
+    let envelope: Envelope = deserialize(&payload_bytes, capabilities.format)?;
+    debug!("Received envelope: {:?}", envelope);
 
-    Ok(message)
+    Ok(envelope)
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "async"))]
 mod test {
     use super::*;
 
@@ -107,6 +746,8 @@ mod test {
     use tokio::task;
 
     use crate::network::platform::socket::Stream as PueueStream;
+    use crate::task::TaskStatus;
+    use crate::test_helper;
 
     // Implement generic Listener/Stream traits, so we can test stuff on normal TCP
     #[async_trait]
@@ -155,4 +796,196 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_compressed_message() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let listener: GenericListener = Box::new(listener);
+
+        let message = create_success_message("a".repeat(100_000));
+        let sent_message = message.clone();
+        task::spawn(async move {
+            let mut stream = listener.accept().await.unwrap();
+            send_message_compressed(sent_message, Compression::Snap, &mut stream)
+                .await
+                .unwrap();
+        });
+
+        let mut client: GenericStream = Box::new(TcpStream::connect(&addr).await?);
+        assert_eq!(receive_message(&mut client).await?, message);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    /// A malicious header must not result in a huge allocation.
+    async fn test_frame_too_large() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let listener: GenericListener = Box::new(listener);
+
+        task::spawn(async move {
+            let mut stream = listener.accept().await.unwrap();
+            let mut header = [0; 8];
+            BigEndian::write_u64(&mut header, u64::MAX);
+            stream.write_all(&header).await.unwrap();
+        });
+
+        let mut client: GenericStream = Box::new(TcpStream::connect(&addr).await?);
+        let result = receive_bytes(&mut client).await;
+        assert!(matches!(result, Err(Error::FrameTooLarge { .. })));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    /// The frame limit is a setting of each connection.
+    async fn test_connection_frame_size() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let listener: GenericListener = Box::new(listener);
+
+        task::spawn(async move {
+            let mut stream = listener.accept().await.unwrap();
+            send_bytes(&[0; 2048], &mut stream).await.unwrap();
+        });
+
+        let mut capabilities = negotiate(&local_handshake(), &local_handshake())?;
+        capabilities.max_frame_size = 1024;
+        let mut client: GenericStream = Box::new(TcpStream::connect(&addr).await?);
+        let result = receive_message_with(&capabilities, &mut client).await;
+        assert!(matches!(
+            result,
+            Err(Error::FrameTooLarge {
+                size: 2048,
+                max: 1024
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chunked_log_response() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let listener: GenericListener = Box::new(listener);
+
+        let task = test_helper::task("default", TaskStatus::Queued);
+        let mut logs = BTreeMap::new();
+        for task_id in 0..2 {
+            let log = TaskLogMessage {
+                task: task.clone(),
+                stdout: Some(vec![task_id as u8; LOG_CHUNK_SIZE + 10]),
+                stderr: None,
+            };
+            logs.insert(task_id, log);
+        }
+
+        let capabilities = negotiate(&local_handshake(), &local_handshake())?;
+        let sent_logs = logs.clone();
+        let daemon_capabilities = capabilities.clone();
+        task::spawn(async move {
+            let mut stream = listener.accept().await.unwrap();
+            send_log_response(sent_logs, &daemon_capabilities, &mut stream)
+                .await
+                .unwrap();
+        });
+
+        let mut client: GenericStream = Box::new(TcpStream::connect(&addr).await?);
+        let received = receive_log_response(&capabilities, &mut client).await?;
+        assert_eq!(received, logs);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handshake() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let listener: GenericListener = Box::new(listener);
+
+        task::spawn(async move {
+            let mut stream = listener.accept().await.unwrap();
+            daemon_handshake(&mut stream).await.unwrap();
+        });
+
+        let mut client: GenericStream = Box::new(TcpStream::connect(&addr).await?);
+        let capabilities = client_handshake(&mut client).await?;
+
+        assert_eq!(capabilities.version, PROTOCOL_VERSION);
+        assert!(capabilities.supports(FEATURE_ARCHIVE));
+        assert_eq!(capabilities.compression, Compression::Zstd);
+        assert_eq!(capabilities.format, WireFormat::Cbor);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    /// The daemon picks up the format of the client's handshake and uses it from then on.
+    async fn test_json_wire_format() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let listener: GenericListener = Box::new(listener);
+
+        task::spawn(async move {
+            let mut stream = listener.accept().await.unwrap();
+            let capabilities = daemon_handshake(&mut stream).await.unwrap();
+            assert_eq!(capabilities.format, WireFormat::Json);
+
+            let message = receive_message_with(&capabilities, &mut stream)
+                .await
+                .unwrap();
+            send_message_with(message, &capabilities, &mut stream)
+                .await
+                .unwrap();
+        });
+
+        let mut client: GenericStream = Box::new(TcpStream::connect(&addr).await?);
+        let capabilities = client_handshake_with_format(WireFormat::Json, &mut client).await?;
+        assert_eq!(capabilities.format, WireFormat::Json);
+
+        // Send a raw JSON message, like a client in another language would do.
+        let payload = br#"{"Remove": [0, 1]}"#;
+        send_bytes(payload, &mut client).await?;
+        let response = receive_bytes(&mut client).await?;
+        assert_eq!(response, br#"{"Remove":[0,1]}"#);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let local = local_handshake();
+        let remote = Handshake {
+            version: PROTOCOL_VERSION + 2,
+            min_version: PROTOCOL_VERSION,
+            features: vec![FEATURE_ARCHIVE.to_string(), "some_new_feature".to_string()],
+        };
+        let capabilities = negotiate(&local, &remote).unwrap();
+        assert_eq!(capabilities.version, PROTOCOL_VERSION);
+        assert_eq!(capabilities.features, vec![FEATURE_ARCHIVE.to_string()]);
+        assert_eq!(capabilities.compression, Compression::None);
+
+        let message = Message::MoveToGroup(MoveToGroupMessage {
+            task_ids: vec![0],
+            group: "test".into(),
+        });
+        assert!(matches!(
+            capabilities.check_message(&message),
+            Err(Error::UnsupportedFeature(_))
+        ));
+        assert!(capabilities.check_message(&Message::Status).is_ok());
+
+        let remote = Handshake {
+            version: PROTOCOL_VERSION + 2,
+            min_version: PROTOCOL_VERSION + 1,
+            features: Vec::new(),
+        };
+        assert!(matches!(
+            negotiate(&local, &remote),
+            Err(Error::IncompatibleProtocol { .. })
+        ));
+    }
 }
@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::Error;
+use crate::network::message::*;
+use crate::state::State;
+
+/// Get the daemon's version from its answer to the shared secret.
+pub(crate) fn daemon_version(answer: Vec<u8>) -> Result<String, Error> {
+    String::from_utf8(answer).map_err(|_| {
+        Error::Connection("Daemon sent an invalid version. Is the secret correct?".into())
+    })
+}
+
+pub(crate) fn add_request(mut message: AddMessage) -> Message {
+    // The daemon then answers with nothing but the id of the new task.
+    message.print_task_id = true;
+
+    Message::Add(message)
+}
+
+/// Get the id of the new task from the answer to an [add_request].
+pub(crate) fn task_id(response: Message) -> Result<usize, Error> {
+    match response {
+        Message::Success(text) => text
+            .trim()
+            .parse()
+            .map_err(|_| Error::UnexpectedResponse(text)),
+        message => Err(unexpected_response(message)),
+    }
+}
+
+pub(crate) fn state(response: Message) -> Result<State, Error> {
+    match response {
+        Message::StatusResponse(state) => Ok(*state),
+        message => Err(unexpected_response(message)),
+    }
+}
+
+pub(crate) fn kill_request(
+    tasks: TaskSelection,
+    signal: Option<Signal>,
+    escalation: Option<KillEscalation>,
+) -> Message {
+    Message::Kill(KillMessage {
+        tasks,
+        children: false,
+        signal,
+        process_group: false,
+        escalation,
+    })
+}
+
+/// Get the daemon's summary of a request, which may only partially succeed.
+pub(crate) fn summary(response: Message) -> Result<String, Error> {
+    match response {
+        Message::Success(text) => Ok(text),
+        Message::PartialSuccess(result) => Ok(result.text),
+        message => Err(unexpected_response(message)),
+    }
+}
+
+/// Get the daemon's summary of a request, which either succeeds completely or fails.
+pub(crate) fn success(response: Message) -> Result<String, Error> {
+    match response {
+        Message::Success(text) => Ok(text),
+        message => Err(unexpected_response(message)),
+    }
+}
+
+pub(crate) fn envs(response: Message) -> Result<BTreeMap<usize, HashMap<String, String>>, Error> {
+    match response {
+        Message::EnvResponse(response) => Ok(response.envs),
+        message => Err(unexpected_response(message)),
+    }
+}
+
+pub(crate) fn logs_request(task_ids: Vec<usize>, lines: Option<usize>) -> Message {
+    Message::Log(LogRequestMessage {
+        task_ids,
+        send_logs: true,
+        lines,
+    })
+}
+
+/// Whether a stream of responses ended. \
+/// This interprets the responses of the async and blocking streams.
+///
+/// A [Message::Success] ends a stream, as well as any error or unexpected response.
+#[derive(Default)]
+pub(crate) struct StreamState {
+    finished: bool,
+}
+
+impl StreamState {
+    pub(crate) fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Handle the next response of a followed task's output.
+    pub(crate) fn log(
+        &mut self,
+        response: Result<Message, Error>,
+    ) -> Result<Option<String>, Error> {
+        self.handle(response, |message| match message {
+            Message::Stream(text) => Ok(Some(text)),
+            message => Err(unexpected_response(message)),
+        })
+    }
+
+    /// Handle the next response of followed raw outputs.
+    /// The request is advanced, so it resumes the stream right after the received chunk.
+    pub(crate) fn output(
+        &mut self,
+        request: &mut MultiStreamRequestMessage,
+        response: Result<Message, Error>,
+    ) -> Result<Option<StreamChunkMessage>, Error> {
+        self.handle(response, |message| match message {
+            Message::StreamChunk(chunk) => {
+                request.advance(&chunk);
+                Ok(Some(chunk))
+            }
+            message => Err(unexpected_response(message)),
+        })
+    }
+
+    /// Handle the next response of a wait request.
+    /// The tasks that didn't reach the condition are stored in `failures`.
+    pub(crate) fn wait(
+        &mut self,
+        failures: &mut Vec<FailureMessage>,
+        response: Result<Message, Error>,
+    ) -> Result<Option<WaitUpdateMessage>, Error> {
+        self.handle(response, |message| match message {
+            Message::WaitUpdate(update) => Ok(Some(update)),
+            Message::PartialSuccess(result) => {
+                *failures = result.failures;
+                Ok(None)
+            }
+            message => Err(unexpected_response(message)),
+        })
+    }
+
+    /// Handle the next event of a subscription.
+    /// The stream ends after the daemon announced its shutdown.
+    pub(crate) fn event(
+        &mut self,
+        response: Result<Message, Error>,
+    ) -> Result<Option<Event>, Error> {
+        let event = self.handle(response, |message| match message {
+            Message::Event(event) => Ok(Some(event)),
+            message => Err(unexpected_response(message)),
+        })?;
+        if matches!(event, Some(Event::DaemonShutdown(_))) {
+            self.finished = true;
+        }
+
+        Ok(event)
+    }
+
+    /// `item` gets the item from a response or returns `None`, if the response ends the stream.
+    fn handle<T>(
+        &mut self,
+        response: Result<Message, Error>,
+        item: impl FnOnce(Message) -> Result<Option<T>, Error>,
+    ) -> Result<Option<T>, Error> {
+        let result = match response {
+            Ok(Message::Success(_)) => Ok(None),
+            Ok(message) => item(message),
+            Err(err) => Err(err),
+        };
+        if !matches!(result, Ok(Some(_))) {
+            self.finished = true;
+        }
+
+        result
+    }
+}
+
+fn unexpected_response(message: Message) -> Error {
+    Error::UnexpectedResponse(format!("{:?}", message))
+}
@@ -1,11 +1,16 @@
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
+#[cfg(feature = "async")]
 use std::sync::Arc;
 
+#[cfg(feature = "async")]
 use tokio_rustls::{TlsAcceptor, TlsConnector};
 
-use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig};
+use rustls::{Certificate, ClientConfig, RootCertStore};
+#[cfg(feature = "async")]
+use rustls::{PrivateKey, ServerConfig};
+#[cfg(feature = "async")]
 use rustls_pemfile::{pkcs8_private_keys, rsa_private_keys};
 
 use crate::error::Error;
@@ -14,7 +19,16 @@ use crate::settings::Shared;
 /// Initialize our client [TlsConnector]. \
 /// 1. Trust our own CA. ONLY our own CA.
 /// 2. Set the client certificate and key
+#[cfg(feature = "async")]
 pub async fn get_tls_connector(settings: &Shared) -> Result<TlsConnector, Error> {
+    let config = get_client_tls_config(settings)?;
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Build the client configuration, which only trusts our own CA. \
+/// This is shared by the async and the blocking client.
+pub(crate) fn get_client_tls_config(settings: &Shared) -> Result<ClientConfig, Error> {
     // Only trust server-certificates signed with our own CA.
     let ca = load_ca(&settings.daemon_cert())?;
     let mut cert_store = RootCertStore::empty();
@@ -30,11 +44,12 @@ pub async fn get_tls_connector(settings: &Shared) -> Result<TlsConnector, Error>
         .with_root_certificates(cert_store)
         .with_no_client_auth();
 
-    Ok(TlsConnector::from(Arc::new(config)))
+    Ok(config)
 }
 
 /// Configure the server using rusttls. \
 /// A TLS server needs a certificate and a fitting private key.
+#[cfg(feature = "async")]
 pub fn get_tls_listener(settings: &Shared) -> Result<TlsAcceptor, Error> {
     // Set the server-side key and certificate that should be used for all communication.
     let certs = load_certs(&settings.daemon_cert())?;
@@ -55,6 +70,7 @@ pub fn get_tls_listener(settings: &Shared) -> Result<TlsAcceptor, Error> {
 }
 
 /// Load the passed certificates file
+#[cfg(feature = "async")]
 fn load_certs(path: &Path) -> Result<Vec<Certificate>, Error> {
     let file = File::open(path)
         .map_err(|_| Error::FileNotFound(format!("Cannot open cert {:?}", path)))?;
@@ -69,6 +85,7 @@ fn load_certs(path: &Path) -> Result<Vec<Certificate>, Error> {
 
 /// Load the passed keys file.
 /// Only the first key will be used. It should match the certificate.
+#[cfg(feature = "async")]
 fn load_key(path: &Path) -> Result<PrivateKey, Error> {
     let file =
         File::open(path).map_err(|_| Error::FileNotFound(format!("Cannot open key {:?}", path)))?;
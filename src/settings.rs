@@ -89,6 +89,12 @@ pub struct Daemon {
     pub callback: Option<String>,
     /// The amount of log lines from stdout/stderr that are passed to the callback command.
     pub callback_log_lines: usize,
+    /// The maximum amount of cleaned tasks that are kept in the archive.
+    #[serde(default = "default_archive_max_tasks")]
+    pub archive_max_tasks: usize,
+    /// The maximum size of all archived logs in bytes.
+    #[serde(default = "default_archive_max_log_size")]
+    pub archive_max_log_size: usize,
     /// The legacy configuration for groups
     #[serde(skip_serializing)]
     #[deprecated(
@@ -98,6 +104,17 @@ pub struct Daemon {
     pub groups: Option<HashMap<String, i64>>,
 }
 
+/// The default for `daemon.archive_max_tasks`. \
+/// This is also used for states that have been saved before the setting existed.
+fn default_archive_max_tasks() -> usize {
+    1000
+}
+
+/// The default for `daemon.archive_max_log_size` (256 MiB).
+fn default_archive_max_log_size() -> usize {
+    256 * 1024 * 1024
+}
+
 /// The parent settings struct. \
 /// This contains all other setting structs.
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
@@ -226,6 +243,18 @@ impl Settings {
             .set_default("daemon.callback", None::<String>)
             .unwrap();
         config.set_default("daemon.callback_log_lines", 10).unwrap();
+        config
+            .set_default(
+                "daemon.archive_max_tasks",
+                default_archive_max_tasks() as i64,
+            )
+            .unwrap();
+        config
+            .set_default(
+                "daemon.archive_max_log_size",
+                default_archive_max_log_size() as i64,
+            )
+            .unwrap();
         config
             .set_default("daemon.groups", None::<HashMap<String, i64>>)
             .unwrap();
@@ -1,19 +1,36 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
+use chrono::prelude::*;
+use chrono::Duration;
 use serde::{de, Deserializer};
 use serde_derive::{Deserialize, Serialize};
 use serde_json::Value;
+use strum_macros::{Display, EnumString};
 
-use crate::error::Error;
+use crate::aliasing::insert_alias;
+use crate::error::{Error, ErrorCode, FailureMessage};
 use crate::settings::Settings;
-use crate::task::{Task, TaskStatus};
+use crate::task::{Task, TaskChange, TaskStatus};
 
 pub const PUEUE_DEFAULT_GROUP: &str = "default";
 
 pub type SharedState = Arc<Mutex<State>>;
 
+/// This enum is used to express a selection of tasks.
+/// As commands can be executed on various sets of tasks, we need some kind of datastructure to
+/// explicitly and unambiguously specify the selection.
+///
+/// A `Group` selection also includes all tasks of the group's subgroups.
+/// Use [State::task_ids_in_selection] to resolve a selection.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub enum TaskSelection {
+    TaskIds(Vec<usize>),
+    Group(String),
+    All,
+}
+
 /// Represents the current status of a group.
 /// Each group acts as a queue and can be managed individually.
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
@@ -27,6 +44,123 @@ pub enum GroupStatus {
 pub struct Group {
     pub status: GroupStatus,
     pub parallel_tasks: usize,
+    /// The time windows during which this group is allowed to start new tasks. \
+    /// If this is empty, tasks may be started at any time.
+    #[serde(default)]
+    pub time_windows: Vec<TimeWindow>,
+    /// The point in time at which a paused group should automatically be resumed.
+    #[serde(default)]
+    pub resume_at: Option<DateTime<Local>>,
+    /// The order in which queued tasks of this group are started.
+    #[serde(default)]
+    pub ordering: QueueOrdering,
+    /// The name of this group's parent group. \
+    /// A group is limited by the parallel limits and the status of all its ancestors.
+    #[serde(default)]
+    pub parent: Option<String>,
+}
+
+impl Group {
+    /// Check whether the given point in time lies inside one of the group's time windows. \
+    /// Groups without any time windows are always active.
+    pub fn is_in_time_window(&self, time: &DateTime<Local>) -> bool {
+        self.time_windows.is_empty() || self.time_windows.iter().any(|window| window.contains(time))
+    }
+
+    /// Whether this group may start new tasks at the given point in time. \
+    /// That's the case if the group is running and the time lies inside one of its time windows.
+    pub fn may_start_tasks_at(&self, time: &DateTime<Local>) -> bool {
+        self.status == GroupStatus::Running && self.is_in_time_window(time)
+    }
+
+    /// Return the next point in time after `time` at which one of the group's time windows opens.
+    ///
+    /// Returns `None`, if the group doesn't have any time windows.
+    pub fn next_window_start(&self, time: &DateTime<Local>) -> Option<DateTime<Local>> {
+        self.time_windows
+            .iter()
+            .filter_map(|window| window.next_start(time))
+            .min()
+    }
+}
+
+/// The order in which the queued tasks of a group are started.
+#[derive(PartialEq, Clone, Copy, Debug, Default, Deserialize, Serialize, Display, EnumString)]
+pub enum QueueOrdering {
+    /// Oldest tasks first, i.e. ordered by ascending task id.
+    #[default]
+    #[strum(serialize = "Fifo", serialize = "fifo")]
+    Fifo,
+    /// Newest tasks first, i.e. ordered by descending task id.
+    #[strum(serialize = "Lifo", serialize = "lifo")]
+    Lifo,
+    /// Tasks with the shortest expected runtime first. \
+    /// The expected runtime of a task is the duration of the last finished task with the same
+    /// command. Tasks without such a task are started last in FIFO order.
+    #[strum(serialize = "ShortestFirst", serialize = "shortest_first")]
+    ShortestFirst,
+}
+
+/// A recurring time window, during which a group is allowed to start new tasks.
+///
+/// The window opens at `start` on each of the given `weekdays` and closes at `end`. \
+/// If `end` isn't after `start`, the window spans midnight and closes on the following day. \
+/// An empty list of `weekdays` means that the window opens on every day of the week.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct TimeWindow {
+    #[serde(default)]
+    pub weekdays: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    /// Check whether the given point in time lies inside this window.
+    pub fn contains(&self, time: &DateTime<Local>) -> bool {
+        let weekday = time.weekday();
+        let current = time.time();
+
+        if self.start < self.end {
+            return self.opens_on(weekday) && self.start <= current && current < self.end;
+        }
+
+        // The window spans midnight.
+        // It's either been opened today or it's still open since yesterday.
+        (self.opens_on(weekday) && current >= self.start)
+            || (self.opens_on(weekday.pred()) && current < self.end)
+    }
+
+    /// Return the next point in time after `time` at which this window opens.
+    pub fn next_start(&self, time: &DateTime<Local>) -> Option<DateTime<Local>> {
+        let today = time.date_naive();
+
+        // A window opens at least once per week, so we only need to look at the next eight days.
+        for offset in 0..=7 {
+            let date = today + Duration::days(offset);
+            if !self.opens_on(date.weekday()) {
+                continue;
+            }
+
+            // Skip start times that don't exist on this day due to DST changes.
+            let start = match Local
+                .from_local_datetime(&date.and_time(self.start))
+                .earliest()
+            {
+                Some(start) => start,
+                None => continue,
+            };
+            if start > *time {
+                return Some(start);
+            }
+        }
+
+        None
+    }
+
+    /// Whether this window opens on the given day of the week.
+    fn opens_on(&self, weekday: Weekday) -> bool {
+        self.weekdays.is_empty() || self.weekdays.contains(&weekday)
+    }
 }
 
 /// This is the full representation of the current state of the Pueue daemon.
@@ -99,6 +233,10 @@ where
                             Group {
                                 status: GroupStatus::Paused,
                                 parallel_tasks: 1,
+                                time_windows: Vec::new(),
+                                resume_at: None,
+                                ordering: QueueOrdering::default(),
+                                parent: None,
                             },
                         );
                     }
@@ -145,6 +283,143 @@ impl State {
         };
     }
 
+    /// Apply the changes to a task. \
+    /// Either all changes are valid and applied or the first invalid one is returned as
+    /// [Error::Failure].
+    ///
+    /// Locked tasks are being edited by a client and can't be changed at all.
+    /// The label and kill escalation of any other task may be changed, as they don't affect its
+    /// process. All other properties can only be changed for
+    /// queued or stashed tasks. A task may only be enqueued at a specific time, if it's
+    /// stashed. Its group and dependencies have to exist and the dependencies may not contain
+    /// a cycle.
+    pub fn edit_task(&mut self, task_id: usize, changes: Vec<TaskChange>) -> Result<(), Error> {
+        let failure = |code: ErrorCode, text: String| {
+            Error::Failure(FailureMessage {
+                code,
+                task_ids: vec![task_id],
+                text,
+            })
+        };
+
+        let task = self.tasks.get(&task_id).ok_or_else(|| {
+            failure(
+                ErrorCode::TaskNotFound,
+                format!("Task {} doesn't exist", task_id),
+            )
+        })?;
+        let status = &task.status;
+        if *status == TaskStatus::Locked {
+            return Err(failure(
+                ErrorCode::InvalidTaskStatus,
+                format!("Task {} is being edited", task_id),
+            ));
+        }
+
+        for change in changes.iter() {
+            if matches!(change, TaskChange::Label(_) | TaskChange::KillEscalation(_)) {
+                continue;
+            }
+            match status {
+                TaskStatus::Queued | TaskStatus::Stashed { .. } => (),
+                TaskStatus::Running | TaskStatus::Paused => {
+                    return Err(failure(
+                        ErrorCode::TaskRunning,
+                        format!("Task {} is already running", task_id),
+                    ))
+                }
+                _ => {
+                    return Err(failure(
+                        ErrorCode::InvalidTaskStatus,
+                        format!("Task {} can no longer be edited", task_id),
+                    ))
+                }
+            }
+
+            match change {
+                TaskChange::Command(command) if command.trim().is_empty() => {
+                    return Err(failure(
+                        ErrorCode::InvalidRequest,
+                        "The command cannot be empty".into(),
+                    ));
+                }
+                TaskChange::Group(group) if !self.groups.contains_key(group) => {
+                    return Err(failure(
+                        ErrorCode::GroupNotFound,
+                        format!("Group \"{}\" doesn't exist", group),
+                    ));
+                }
+                TaskChange::Dependencies(dependencies) => {
+                    for dependency in dependencies {
+                        if !self.tasks.contains_key(dependency) {
+                            return Err(failure(
+                                ErrorCode::InvalidDependency,
+                                format!("Dependency {} doesn't exist", dependency),
+                            ));
+                        }
+                        if self.depends_on(*dependency, task_id) {
+                            return Err(failure(
+                                ErrorCode::InvalidDependency,
+                                format!(
+                                    "Task {} cannot depend on {}, as this would create a cycle",
+                                    task_id, dependency
+                                ),
+                            ));
+                        }
+                    }
+                }
+                TaskChange::EnqueueAt(_) if !matches!(status, TaskStatus::Stashed { .. }) => {
+                    return Err(failure(
+                        ErrorCode::InvalidTaskStatus,
+                        format!("Task {} has to be stashed to be enqueued later", task_id),
+                    ));
+                }
+                _ => (),
+            }
+        }
+
+        let task = self.tasks.get_mut(&task_id).unwrap();
+        for change in changes {
+            match change {
+                TaskChange::Command(command) => {
+                    task.command = insert_alias(command.clone());
+                    task.original_command = command;
+                }
+                TaskChange::Path(path) => task.path = path,
+                TaskChange::Label(label) => task.label = label,
+                TaskChange::Envs(envs) => task.envs = envs,
+                TaskChange::Group(group) => task.group = group,
+                TaskChange::Dependencies(dependencies) => task.dependencies = dependencies,
+                TaskChange::KillEscalation(escalation) => task.kill_escalation = escalation,
+                TaskChange::EnqueueAt(enqueue_at) => {
+                    task.status = TaskStatus::Stashed { enqueue_at }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check whether a task depends on another task, either directly or transitively.
+    pub fn depends_on(&self, task_id: usize, dependency: usize) -> bool {
+        let mut visited = Vec::new();
+        let mut pending = vec![task_id];
+        while let Some(current) = pending.pop() {
+            if current == dependency {
+                return true;
+            }
+            if visited.contains(&current) {
+                continue;
+            }
+            visited.push(current);
+            if let Some(task) = self.tasks.get(&current) {
+                pending.extend(task.dependencies.iter().cloned());
+            }
+        }
+
+        false
+    }
+
     /// Add a new group to the daemon. \
     /// This also check if the given group already exists.
     /// Create a state.group entry and a settings.group entry, if it doesn't.
@@ -152,6 +427,10 @@ impl State {
         self.groups.entry(name.into()).or_insert(Group {
             status: GroupStatus::Running,
             parallel_tasks: 1,
+            time_windows: Vec::new(),
+            resume_at: None,
+            ordering: QueueOrdering::default(),
+            parent: None,
         })
     }
 
@@ -165,7 +444,14 @@ impl State {
             ));
         }
 
-        self.groups.remove(group);
+        let parent = self.groups.remove(group).and_then(|removed| removed.parent);
+
+        // Attach all child groups to the parent of the removed group.
+        for (_, child) in self.groups.iter_mut() {
+            if child.parent.as_deref() == Some(group) {
+                child.parent = parent.clone();
+            }
+        }
 
         // Reset all tasks with removed group to the default.
         for (_, task) in self.tasks.iter_mut() {
@@ -177,13 +463,260 @@ impl State {
         Ok(())
     }
 
+    /// Rename a group. \
+    /// The group keeps its configuration and all of its tasks are moved to the new name.
+    /// Renaming a group to its current name doesn't change anything.
+    pub fn rename_group(&mut self, name: &str, new_name: &str) -> Result<(), Error> {
+        if name.eq(PUEUE_DEFAULT_GROUP) {
+            return Err(group_failure(
+                ErrorCode::InvalidRequest,
+                "You cannot rename the default group.".into(),
+            ));
+        }
+        if !self.groups.contains_key(name) {
+            return Err(group_not_found(name));
+        }
+        if name == new_name {
+            return Ok(());
+        }
+        if self.groups.contains_key(new_name) {
+            return Err(group_failure(
+                ErrorCode::GroupExists,
+                format!("Group \"{}\" already exists.", new_name),
+            ));
+        }
+
+        if let Some(group) = self.groups.remove(name) {
+            self.groups.insert(new_name.into(), group);
+        }
+
+        for (_, child) in self.groups.iter_mut() {
+            if child.parent.as_deref() == Some(name) {
+                child.parent = Some(new_name.into());
+            }
+        }
+
+        for (_, task) in self.tasks.iter_mut() {
+            if task.group.eq(name) {
+                task.group = new_name.into();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move tasks to another group. \
+    /// Task ids and dependencies stay untouched. Running tasks cannot be moved.
+    ///
+    /// Returns a tuple of all (moved_task_ids, non_moved_task_ids).
+    pub fn move_tasks_to_group(
+        &mut self,
+        task_ids: Vec<usize>,
+        group: &str,
+    ) -> Result<(Vec<usize>, Vec<usize>), Error> {
+        if !self.groups.contains_key(group) {
+            return Err(group_not_found(group));
+        }
+
+        let (movable, immovable) = self.filter_tasks(|task| !task.is_running(), Some(task_ids));
+        for task_id in movable.iter() {
+            if let Some(task) = self.tasks.get_mut(task_id) {
+                task.group = group.into();
+            }
+        }
+
+        Ok((movable, immovable))
+    }
+
+    /// Set an environment variable on tasks. Running tasks cannot be changed.
+    ///
+    /// Returns a tuple of all (changed_task_ids, unchanged_task_ids).
+    pub fn set_task_env(
+        &mut self,
+        task_ids: Vec<usize>,
+        key: &str,
+        value: &str,
+    ) -> (Vec<usize>, Vec<usize>) {
+        self.change_task_envs(task_ids, |envs| {
+            envs.insert(key.into(), value.into());
+        })
+    }
+
+    /// Remove an environment variable from tasks. Running tasks cannot be changed.
+    ///
+    /// Returns a tuple of all (changed_task_ids, unchanged_task_ids).
+    pub fn unset_task_env(&mut self, task_ids: Vec<usize>, key: &str) -> (Vec<usize>, Vec<usize>) {
+        self.change_task_envs(task_ids, |envs| {
+            envs.remove(key);
+        })
+    }
+
+    /// Apply a change to the environment variables of all given tasks that aren't running.
+    fn change_task_envs<F>(&mut self, task_ids: Vec<usize>, change: F) -> (Vec<usize>, Vec<usize>)
+    where
+        F: Fn(&mut HashMap<String, String>),
+    {
+        let (changeable, unchangeable) =
+            self.filter_tasks(|task| !task.is_running(), Some(task_ids));
+        for task_id in changeable.iter() {
+            if let Some(task) = self.tasks.get_mut(task_id) {
+                change(&mut task.envs);
+            }
+        }
+
+        (changeable, unchangeable)
+    }
+
+    /// Get the environment variables of the given tasks. Non-existing tasks are skipped.
+    pub fn task_envs(&self, task_ids: &[usize]) -> BTreeMap<usize, HashMap<String, String>> {
+        task_ids
+            .iter()
+            .filter_map(|id| self.tasks.get(id).map(|task| (*id, task.envs.clone())))
+            .collect()
+    }
+
+    /// Set or remove the parent of a group.
+    /// This fails, if one of the groups doesn't exist or if the hierarchy would contain a cycle.
+    pub fn set_group_parent(&mut self, name: &str, parent: Option<&str>) -> Result<(), Error> {
+        if !self.groups.contains_key(name) {
+            return Err(group_not_found(name));
+        }
+
+        if let Some(parent) = parent {
+            if !self.groups.contains_key(parent) {
+                return Err(group_not_found(parent));
+            }
+            if parent == name
+                || self
+                    .group_ancestors(parent)
+                    .iter()
+                    .any(|ancestor| ancestor == name)
+            {
+                return Err(group_failure(
+                    ErrorCode::InvalidRequest,
+                    format!(
+                        "Group \"{}\" cannot be a child of its own subgroup \"{}\".",
+                        name, parent
+                    ),
+                ));
+            }
+        }
+
+        if let Some(group) = self.groups.get_mut(name) {
+            group.parent = parent.map(String::from);
+        }
+
+        Ok(())
+    }
+
+    /// Get the names of all ancestors of a group, starting with its direct parent.
+    pub fn group_ancestors(&self, group: &str) -> Vec<String> {
+        let mut ancestors: Vec<String> = Vec::new();
+        let mut current = self
+            .groups
+            .get(group)
+            .and_then(|group| group.parent.as_ref());
+
+        while let Some(parent) = current {
+            // Stop on broken state files with cyclic hierarchies.
+            if parent == group || ancestors.contains(parent) {
+                break;
+            }
+            ancestors.push(parent.clone());
+            current = self
+                .groups
+                .get(parent)
+                .and_then(|group| group.parent.as_ref());
+        }
+
+        ancestors
+    }
+
+    /// Check whether a group is the given ancestor or one of its (indirect) subgroups.
+    pub fn is_in_group_tree(&self, group: &str, ancestor: &str) -> bool {
+        group == ancestor
+            || self
+                .group_ancestors(group)
+                .iter()
+                .any(|name| name == ancestor)
+    }
+
+    /// The status of a group with respect to its ancestors. \
+    /// A group is considered paused, as soon as any of its ancestors is paused.
+    pub fn effective_group_status(&self, group: &str) -> GroupStatus {
+        let paused = std::iter::once(group.to_string())
+            .chain(self.group_ancestors(group))
+            .filter_map(|name| self.groups.get(&name))
+            .any(|group| group.status == GroupStatus::Paused);
+
+        if paused {
+            GroupStatus::Paused
+        } else {
+            GroupStatus::Running
+        }
+    }
+
+    /// The amount of tasks that may additionally be started in a group right now. \
+    /// This respects the parallel limits of all its ancestors, which are shared between
+    /// each ancestor's whole subgroup tree.
+    pub fn effective_parallel_capacity(&self, group: &str) -> usize {
+        if !self.groups.contains_key(group) {
+            return 0;
+        }
+
+        std::iter::once(group.to_string())
+            .chain(self.group_ancestors(group))
+            .filter_map(|name| {
+                let limit = self.groups.get(&name)?.parallel_tasks;
+                let running = self
+                    .tasks
+                    .values()
+                    .filter(|task| task.is_running() && self.is_in_group_tree(&task.group, &name))
+                    .count();
+                Some(limit.saturating_sub(running))
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Get the ids of all tasks that match a [TaskSelection]. \
+    /// A group selection includes all tasks of the group's subgroups.
+    pub fn task_ids_in_selection(&self, selection: &TaskSelection) -> Vec<usize> {
+        match selection {
+            TaskSelection::TaskIds(task_ids) => task_ids
+                .iter()
+                .filter(|id| self.tasks.contains_key(id))
+                .cloned()
+                .collect(),
+            TaskSelection::Group(group) => self
+                .tasks
+                .iter()
+                .filter(|(_, task)| self.is_in_group_tree(&task.group, group))
+                .map(|(id, _)| *id)
+                .collect(),
+            TaskSelection::All => self.tasks.keys().cloned().collect(),
+        }
+    }
+
     /// Set the group status (running/paused) for all groups including the default queue.
+    /// Any scheduled automatic resume is discarded.
     pub fn set_status_for_all_groups(&mut self, status: GroupStatus) {
         for (_, group) in self.groups.iter_mut() {
             group.status = status.clone();
+            group.resume_at = None;
         }
     }
 
+    /// Get the names of all paused groups, whose scheduled resume time has been reached.
+    pub fn groups_due_to_resume(&self, time: &DateTime<Local>) -> Vec<String> {
+        self.groups
+            .iter()
+            .filter(|(_, group)| group.status == GroupStatus::Paused)
+            .filter(|(_, group)| matches!(group.resume_at, Some(resume_at) if resume_at <= *time))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
     /// Get all ids of task inside a specific group.
     pub fn task_ids_in_group(&self, group: &str) -> Vec<usize> {
         self.tasks
@@ -193,6 +726,50 @@ impl State {
             .collect()
     }
 
+    /// Get the ids of all queued tasks of a group in the order in which they should be started.
+    /// The order is determined by the group's [QueueOrdering].
+    pub fn sorted_queued_tasks(&self, group: &str) -> Vec<usize> {
+        let ordering = match self.groups.get(group) {
+            Some(group) => group.ordering,
+            None => return Vec::new(),
+        };
+
+        // The task map is a BTreeMap, which means that the ids are already sorted ascending.
+        let mut task_ids: Vec<usize> = self
+            .tasks
+            .iter()
+            .filter(|(_, task)| task.group == group && task.status == TaskStatus::Queued)
+            .map(|(id, _)| *id)
+            .collect();
+
+        match ordering {
+            QueueOrdering::Fifo => (),
+            QueueOrdering::Lifo => task_ids.reverse(),
+            QueueOrdering::ShortestFirst => {
+                // The sort is stable, so tasks with equal expectations stay in FIFO order.
+                task_ids.sort_by_key(|id| {
+                    let duration = self.expected_duration(&self.tasks[id].command);
+                    (duration.is_none(), duration)
+                });
+            }
+        }
+
+        task_ids
+    }
+
+    /// The expected runtime of a command. \
+    /// This is the runtime of the most recently finished task with the same command.
+    pub fn expected_duration(&self, command: &str) -> Option<Duration> {
+        self.tasks
+            .values()
+            .rev()
+            .filter(|task| task.command == command && task.is_done())
+            .find_map(|task| match (task.start, task.end) {
+                (Some(start), Some(end)) => Some(end - start),
+                _ => None,
+            })
+    }
+
     /// This checks, whether some tasks match the expected filter criteria. \
     /// The first result is the list of task_ids that match these statuses. \
     /// The second result is the list of task_ids that don't match these statuses. \
@@ -269,3 +846,347 @@ impl State {
         (matching, mismatching)
     }
 }
+
+/// A failure of a group operation, which doesn't concern any specific tasks.
+fn group_failure(code: ErrorCode, text: String) -> Error {
+    Error::Failure(FailureMessage {
+        code,
+        task_ids: Vec::new(),
+        text,
+    })
+}
+
+fn group_not_found(group: &str) -> Error {
+    group_failure(
+        ErrorCode::GroupNotFound,
+        format!("Group \"{}\" doesn't exist.", group),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::task::KillEscalation;
+    use crate::task::TaskResult;
+    use crate::test_helper;
+    use pretty_assertions::assert_eq;
+
+    fn failure_code<T: std::fmt::Debug>(result: Result<T, Error>) -> ErrorCode {
+        match result {
+            Err(Error::Failure(failure)) => failure.code,
+            result => panic!("Expected failure, got {:?}", result),
+        }
+    }
+
+    fn local(date: (i32, u32, u32), time: (u32, u32)) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(date.0, date.1, date.2, time.0, time.1, 0)
+            .unwrap()
+    }
+
+    fn nightly_window() -> TimeWindow {
+        TimeWindow {
+            weekdays: vec![Weekday::Mon, Weekday::Tue],
+            start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    /// Windows that span midnight stay open until the next morning.
+    fn test_time_window_spanning_midnight() {
+        let window = nightly_window();
+
+        // 2021-11-01 is a Monday.
+        assert!(!window.contains(&local((2021, 11, 1), (21, 59))));
+        assert!(window.contains(&local((2021, 11, 1), (22, 0))));
+        assert!(window.contains(&local((2021, 11, 2), (5, 59))));
+        assert!(!window.contains(&local((2021, 11, 2), (6, 0))));
+        // The window opened on Tuesday is still open on Wednesday morning.
+        assert!(window.contains(&local((2021, 11, 3), (3, 0))));
+        // But there's no window opening on Wednesday.
+        assert!(!window.contains(&local((2021, 11, 3), (23, 0))));
+        assert!(!window.contains(&local((2021, 11, 4), (3, 0))));
+    }
+
+    #[test]
+    fn test_group_next_window_start() {
+        let mut group = Group {
+            status: GroupStatus::Running,
+            parallel_tasks: 1,
+            time_windows: Vec::new(),
+            resume_at: None,
+            ordering: QueueOrdering::default(),
+            parent: None,
+        };
+        let wednesday = local((2021, 11, 3), (12, 0));
+        assert!(group.may_start_tasks_at(&wednesday));
+        assert_eq!(group.next_window_start(&wednesday), None);
+
+        group.time_windows.push(nightly_window());
+        assert!(!group.may_start_tasks_at(&wednesday));
+        assert_eq!(
+            group.next_window_start(&wednesday),
+            Some(local((2021, 11, 8), (22, 0)))
+        );
+
+        group.status = GroupStatus::Paused;
+        assert!(!group.may_start_tasks_at(&local((2021, 11, 8), (23, 0))));
+    }
+
+    #[test]
+    fn test_groups_due_to_resume() {
+        let mut state = State::new(&Settings::read_with_defaults(&None).unwrap().0, None);
+        let now = local((2021, 11, 3), (12, 0));
+
+        let group = state.create_group("weekend");
+        group.status = GroupStatus::Paused;
+        group.resume_at = Some(local((2021, 11, 3), (11, 0)));
+        let group = state.create_group("later");
+        group.status = GroupStatus::Paused;
+        group.resume_at = Some(local((2021, 11, 4), (8, 0)));
+
+        assert_eq!(
+            state.groups_due_to_resume(&now),
+            vec!["weekend".to_string()]
+        );
+
+        state.set_status_for_all_groups(GroupStatus::Running);
+        assert!(state.groups_due_to_resume(&now).is_empty());
+    }
+
+    #[test]
+    fn test_sorted_queued_tasks() {
+        let mut state = State::new(&Settings::read_with_defaults(&None).unwrap().0, None);
+        let start = local((2021, 11, 3), (12, 0));
+        for (command, duration) in [("slow", 60), ("fast", 5), ("slow", 30), ("unknown", 0)] {
+            let task = Task::new(
+                command.into(),
+                "/tmp".into(),
+                Default::default(),
+                PUEUE_DEFAULT_GROUP.into(),
+                TaskStatus::Queued,
+                Vec::new(),
+                None,
+            );
+            state.add_task(task.clone());
+            // Add a finished task for each known command, to get an expected runtime.
+            if duration > 0 {
+                let finished_id = state.add_task(task);
+                let finished = state.tasks.get_mut(&finished_id).unwrap();
+                finished.status = TaskStatus::Done(TaskResult::Success);
+                finished.start = Some(start);
+                finished.end = Some(start + Duration::seconds(duration));
+            }
+        }
+        // Task ids: slow 0, fast 2, slow 4, unknown 6
+        assert_eq!(
+            state.sorted_queued_tasks(PUEUE_DEFAULT_GROUP),
+            vec![0, 2, 4, 6]
+        );
+
+        let group = state.groups.get_mut(PUEUE_DEFAULT_GROUP).unwrap();
+        group.ordering = QueueOrdering::Lifo;
+        assert_eq!(
+            state.sorted_queued_tasks(PUEUE_DEFAULT_GROUP),
+            vec![6, 4, 2, 0]
+        );
+
+        // The most recent "slow" run took 30 seconds.
+        let group = state.groups.get_mut(PUEUE_DEFAULT_GROUP).unwrap();
+        group.ordering = QueueOrdering::ShortestFirst;
+        assert_eq!(
+            state.sorted_queued_tasks(PUEUE_DEFAULT_GROUP),
+            vec![2, 0, 4, 6]
+        );
+    }
+
+    #[test]
+    fn test_rename_group_and_move_tasks() {
+        let mut state = State::new(&Settings::read_with_defaults(&None).unwrap().0, None);
+        state.create_group("old").parallel_tasks = 3;
+        for status in [TaskStatus::Queued, TaskStatus::Running] {
+            let task = test_helper::task("old", status);
+            state.add_task(task);
+        }
+
+        assert_eq!(
+            failure_code(state.rename_group(PUEUE_DEFAULT_GROUP, "new")),
+            ErrorCode::InvalidRequest
+        );
+        assert_eq!(
+            failure_code(state.rename_group("missing", "new")),
+            ErrorCode::GroupNotFound
+        );
+        assert_eq!(
+            failure_code(state.rename_group("old", PUEUE_DEFAULT_GROUP)),
+            ErrorCode::GroupExists
+        );
+        // Renaming a group to its own name is a no-op.
+        state.rename_group("old", "old").unwrap();
+        assert_eq!(state.task_ids_in_group("old"), vec![0, 1]);
+
+        state.rename_group("old", "new").unwrap();
+        assert!(!state.groups.contains_key("old"));
+        assert_eq!(state.groups["new"].parallel_tasks, 3);
+        assert_eq!(state.task_ids_in_group("new"), vec![0, 1]);
+
+        // The running task and the non-existing task cannot be moved.
+        let (moved, not_moved) = state
+            .move_tasks_to_group(vec![0, 1, 2], PUEUE_DEFAULT_GROUP)
+            .unwrap();
+        assert_eq!(moved, vec![0]);
+        assert_eq!(not_moved, vec![1, 2]);
+        assert_eq!(state.tasks[&0].group, PUEUE_DEFAULT_GROUP);
+        assert_eq!(
+            failure_code(state.move_tasks_to_group(vec![0], "old")),
+            ErrorCode::GroupNotFound
+        );
+    }
+
+    #[test]
+    fn test_task_envs() {
+        let mut state = State::new(&Settings::read_with_defaults(&None).unwrap().0, None);
+        for status in [TaskStatus::Queued, TaskStatus::Running] {
+            let envs = HashMap::from([("TOKEN".to_string(), "expired".to_string())]);
+            let mut task = test_helper::task(PUEUE_DEFAULT_GROUP, status);
+            task.envs = envs;
+            state.add_task(task);
+        }
+
+        // The running task and the non-existing task cannot be changed.
+        let (changed, unchanged) = state.set_task_env(vec![0, 1, 2], "TOKEN", "fresh");
+        assert_eq!(changed, vec![0]);
+        assert_eq!(unchanged, vec![1, 2]);
+        assert_eq!(state.tasks[&0].envs["TOKEN"], "fresh");
+        assert_eq!(state.tasks[&1].envs["TOKEN"], "expired");
+
+        let (changed, _) = state.unset_task_env(vec![0], "TOKEN");
+        assert_eq!(changed, vec![0]);
+
+        let envs = state.task_envs(&[0, 1, 2]);
+        assert_eq!(envs.keys().cloned().collect::<Vec<usize>>(), vec![0, 1]);
+        assert!(envs[&0].is_empty());
+        assert_eq!(envs[&1]["TOKEN"], "expired");
+    }
+
+    #[test]
+    fn test_group_hierarchy() {
+        let mut state = State::new(&Settings::read_with_defaults(&None).unwrap().0, None);
+        state.create_group("team").parallel_tasks = 2;
+        state.create_group("project_a").parallel_tasks = 2;
+        state.create_group("project_b").parallel_tasks = 1;
+        state.set_group_parent("project_a", Some("team")).unwrap();
+        state.set_group_parent("project_b", Some("team")).unwrap();
+        assert_eq!(
+            failure_code(state.set_group_parent("team", Some("project_a"))),
+            ErrorCode::InvalidRequest
+        );
+        assert_eq!(
+            failure_code(state.set_group_parent("team", Some("missing"))),
+            ErrorCode::GroupNotFound
+        );
+        assert_eq!(state.group_ancestors("project_a"), vec!["team".to_string()]);
+
+        for group in ["project_a", "project_b", "project_b"] {
+            let task = test_helper::task(group, TaskStatus::Queued);
+            state.add_task(task);
+        }
+        assert_eq!(state.effective_parallel_capacity("project_a"), 2);
+
+        // A running task in project_b takes one slot of the team-wide limit.
+        state.change_status(1, TaskStatus::Running);
+        assert_eq!(state.effective_parallel_capacity("project_a"), 1);
+        assert_eq!(state.effective_parallel_capacity("project_b"), 0);
+
+        assert_eq!(
+            state.task_ids_in_selection(&TaskSelection::Group("team".into())),
+            vec![0, 1, 2]
+        );
+
+        state.groups.get_mut("team").unwrap().status = GroupStatus::Paused;
+        assert_eq!(
+            state.effective_group_status("project_a"),
+            GroupStatus::Paused
+        );
+
+        // Children of removed groups are attached to the removed group's parent.
+        state.remove_group("team").unwrap();
+        assert_eq!(state.groups["project_a"].parent, None);
+    }
+
+    #[test]
+    fn test_edit_task() {
+        let mut state = State::new(&Settings::read_with_defaults(&None).unwrap().0, None);
+        state.create_group("other");
+        for status in [TaskStatus::Queued, TaskStatus::Queued, TaskStatus::Running] {
+            let task = test_helper::task(PUEUE_DEFAULT_GROUP, status);
+            state.add_task(task);
+        }
+        state.tasks.get_mut(&1).unwrap().dependencies = vec![0];
+
+        state
+            .edit_task(
+                0,
+                vec![
+                    TaskChange::Label(Some("label".into())),
+                    TaskChange::Group("other".into()),
+                ],
+            )
+            .unwrap();
+        assert_eq!(state.tasks[&0].label, Some("label".into()));
+        assert_eq!(state.tasks[&0].group, "other");
+
+        // Invalid edits aren't applied at all.
+        let result = state.edit_task(
+            0,
+            vec![
+                TaskChange::Path("/".into()),
+                TaskChange::Dependencies(vec![1]),
+            ],
+        );
+        assert_eq!(failure_code(result), ErrorCode::InvalidDependency);
+        assert_eq!(state.tasks[&0].path, "/tmp");
+
+        let result = state.edit_task(0, vec![TaskChange::Group("unknown".into())]);
+        assert_eq!(failure_code(result), ErrorCode::GroupNotFound);
+        let result = state.edit_task(0, vec![TaskChange::EnqueueAt(None)]);
+        assert_eq!(failure_code(result), ErrorCode::InvalidTaskStatus);
+
+        // Only the label of a running task can be changed.
+        let result = state.edit_task(2, vec![TaskChange::Command("ls -al".into())]);
+        assert_eq!(failure_code(result), ErrorCode::TaskRunning);
+        state
+            .edit_task(
+                2,
+                vec![
+                    TaskChange::Label(Some("running".into())),
+                    TaskChange::KillEscalation(Some(KillEscalation::default())),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            state.tasks[&2].kill_escalation,
+            Some(KillEscalation::default())
+        );
+
+        let task = state.tasks.get_mut(&0).unwrap();
+        task.status = TaskStatus::Stashed { enqueue_at: None };
+        let enqueue_at = local((2021, 11, 3), (12, 0));
+        state
+            .edit_task(0, vec![TaskChange::EnqueueAt(Some(enqueue_at))])
+            .unwrap();
+        assert_eq!(
+            state.tasks[&0].status,
+            TaskStatus::Stashed {
+                enqueue_at: Some(enqueue_at)
+            }
+        );
+
+        // Locked tasks are being edited by another client.
+        state.tasks.get_mut(&0).unwrap().status = TaskStatus::Locked;
+        let result = state.edit_task(0, vec![TaskChange::Label(None)]);
+        assert_eq!(failure_code(result), ErrorCode::InvalidTaskStatus);
+    }
+}
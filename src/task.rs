@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use chrono::prelude::*;
 use serde_derive::{Deserialize, Serialize};
-use strum_macros::Display;
+use strum_macros::{Display, EnumString};
 
 use crate::{aliasing::insert_alias, state::PUEUE_DEFAULT_GROUP};
 
@@ -42,6 +42,159 @@ pub enum TaskResult {
     DependencyFailed,
 }
 
+/// All standard POSIX signals.
+///
+/// Signals are parsed from their name in any case, with or without the `SIG` prefix.
+/// Signals that have the same number on all platforms can be parsed from it as well. `SigCont`
+/// and `SigStop` are also parsed from `18` and `19`, as they always have been.
+/// They're displayed with their variant's name, e.g. `SigInt`.
+///
+/// This is also needed for usage in clap, since nix's Signal doesn't implement
+/// [std::fmt::Display] and [std::str::FromStr].
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize, Display, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum Signal {
+    #[strum(to_string = "SigHup", serialize = "hup", serialize = "1")]
+    SigHup,
+    #[strum(to_string = "SigInt", serialize = "int", serialize = "2")]
+    SigInt,
+    #[strum(to_string = "SigQuit", serialize = "quit", serialize = "3")]
+    SigQuit,
+    #[strum(to_string = "SigIll", serialize = "ill", serialize = "4")]
+    SigIll,
+    #[strum(to_string = "SigTrap", serialize = "trap", serialize = "5")]
+    SigTrap,
+    #[strum(to_string = "SigAbrt", serialize = "abrt", serialize = "6")]
+    SigAbrt,
+    #[strum(to_string = "SigBus", serialize = "bus")]
+    SigBus,
+    #[strum(to_string = "SigFpe", serialize = "fpe", serialize = "8")]
+    SigFpe,
+    #[strum(to_string = "SigKill", serialize = "kill", serialize = "9")]
+    SigKill,
+    #[strum(to_string = "SigUsr1", serialize = "usr1")]
+    SigUsr1,
+    #[strum(to_string = "SigSegv", serialize = "segv", serialize = "11")]
+    SigSegv,
+    #[strum(to_string = "SigUsr2", serialize = "usr2")]
+    SigUsr2,
+    #[strum(to_string = "SigPipe", serialize = "pipe", serialize = "13")]
+    SigPipe,
+    #[strum(to_string = "SigAlrm", serialize = "alrm", serialize = "14")]
+    SigAlrm,
+    #[strum(to_string = "SigTerm", serialize = "term", serialize = "15")]
+    SigTerm,
+    #[strum(to_string = "SigChld", serialize = "chld")]
+    SigChld,
+    #[strum(to_string = "SigCont", serialize = "cont", serialize = "18")]
+    SigCont,
+    #[strum(to_string = "SigStop", serialize = "stop", serialize = "19")]
+    SigStop,
+    #[strum(to_string = "SigTstp", serialize = "tstp")]
+    SigTstp,
+    #[strum(to_string = "SigTtin", serialize = "ttin")]
+    SigTtin,
+    #[strum(to_string = "SigTtou", serialize = "ttou")]
+    SigTtou,
+    #[strum(to_string = "SigUrg", serialize = "urg")]
+    SigUrg,
+    #[strum(to_string = "SigXcpu", serialize = "xcpu")]
+    SigXcpu,
+    #[strum(to_string = "SigXfsz", serialize = "xfsz")]
+    SigXfsz,
+    #[strum(to_string = "SigVtalrm", serialize = "vtalrm")]
+    SigVtalrm,
+    #[strum(to_string = "SigProf", serialize = "prof")]
+    SigProf,
+    #[strum(to_string = "SigSys", serialize = "sys")]
+    SigSys,
+}
+
+impl Signal {
+    /// The number of this signal on the current platform.
+    #[cfg(not(target_os = "windows"))]
+    pub fn number(&self) -> i32 {
+        match self {
+            Signal::SigHup => libc::SIGHUP,
+            Signal::SigInt => libc::SIGINT,
+            Signal::SigQuit => libc::SIGQUIT,
+            Signal::SigIll => libc::SIGILL,
+            Signal::SigTrap => libc::SIGTRAP,
+            Signal::SigAbrt => libc::SIGABRT,
+            Signal::SigBus => libc::SIGBUS,
+            Signal::SigFpe => libc::SIGFPE,
+            Signal::SigKill => libc::SIGKILL,
+            Signal::SigUsr1 => libc::SIGUSR1,
+            Signal::SigSegv => libc::SIGSEGV,
+            Signal::SigUsr2 => libc::SIGUSR2,
+            Signal::SigPipe => libc::SIGPIPE,
+            Signal::SigAlrm => libc::SIGALRM,
+            Signal::SigTerm => libc::SIGTERM,
+            Signal::SigChld => libc::SIGCHLD,
+            Signal::SigCont => libc::SIGCONT,
+            Signal::SigStop => libc::SIGSTOP,
+            Signal::SigTstp => libc::SIGTSTP,
+            Signal::SigTtin => libc::SIGTTIN,
+            Signal::SigTtou => libc::SIGTTOU,
+            Signal::SigUrg => libc::SIGURG,
+            Signal::SigXcpu => libc::SIGXCPU,
+            Signal::SigXfsz => libc::SIGXFSZ,
+            Signal::SigVtalrm => libc::SIGVTALRM,
+            Signal::SigProf => libc::SIGPROF,
+            Signal::SigSys => libc::SIGSYS,
+        }
+    }
+
+    /// Whether this signal has been understood by all versions of the protocol.
+    pub(crate) fn is_legacy(&self) -> bool {
+        matches!(
+            self,
+            Signal::SigInt | Signal::SigKill | Signal::SigTerm | Signal::SigCont | Signal::SigStop
+        )
+    }
+}
+
+/// Kill a task in two steps. \
+/// The first signal is sent right away. If the task is still running after the grace period,
+/// the follow-up signal is sent.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct KillEscalation {
+    pub signal: Signal,
+    /// The number of seconds the task gets to exit after the first signal.
+    pub grace_period: u64,
+    pub follow_up: Signal,
+}
+
+impl Default for KillEscalation {
+    /// `SIGTERM` and `SIGKILL` after ten seconds.
+    fn default() -> Self {
+        KillEscalation {
+            signal: Signal::SigTerm,
+            grace_period: 10,
+            follow_up: Signal::SigKill,
+        }
+    }
+}
+
+/// A change of a single property of a task.
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub enum TaskChange {
+    Command(String),
+    Path(String),
+    /// Set or remove the label.
+    Label(Option<String>),
+    /// Replace all environment variables.
+    Envs(HashMap<String, String>),
+    /// Move the task to another group.
+    Group(String),
+    /// Replace all dependencies.
+    Dependencies(Vec<usize>),
+    /// Set or remove the time at which a stashed task is enqueued.
+    EnqueueAt(Option<DateTime<Local>>),
+    /// Set or remove the default escalation to kill the task.
+    KillEscalation(Option<KillEscalation>),
+}
+
 /// Representation of a task.
 /// start will be set the second the task starts processing.
 /// `result`, `output` and `end` won't be initialized, until the task has finished.
@@ -62,6 +215,10 @@ pub struct Task {
     pub prev_status: TaskStatus,
     pub start: Option<DateTime<Local>>,
     pub end: Option<DateTime<Local>>,
+    /// The escalation that's used to kill this task on a `Reset` or a `DaemonShutdown`. \
+    /// Tasks without one are killed right away.
+    #[serde(default)]
+    pub kill_escalation: Option<KillEscalation>,
 }
 
 impl Task {
@@ -90,6 +247,7 @@ impl Task {
             prev_status: starting_status,
             start: None,
             end: None,
+            kill_escalation: None,
         }
     }
 
@@ -108,6 +266,7 @@ impl Task {
             prev_status: TaskStatus::Queued,
             start: None,
             end: None,
+            kill_escalation: task.kill_escalation.clone(),
         }
     }
 
@@ -144,4 +303,58 @@ impl Task {
     pub fn is_in_default_group(&self) -> bool {
         self.group.eq(PUEUE_DEFAULT_GROUP)
     }
+
+    /// The escalation to kill this task with. \
+    /// An escalation of the kill request takes precedence over the task's default escalation.
+    pub fn kill_escalation_or<'a>(
+        &'a self,
+        escalation: Option<&'a KillEscalation>,
+    ) -> Option<&'a KillEscalation> {
+        escalation.or(self.kill_escalation.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use crate::test_helper;
+
+    #[test]
+    fn test_signal_parsing() {
+        for name in ["SigHup", "SIGHUP", "sighup", "hup", "1"] {
+            assert_eq!(Signal::from_str(name), Ok(Signal::SigHup));
+        }
+        assert_eq!(Signal::SigInt.to_string(), "SigInt");
+        assert_eq!(Signal::from_str("15"), Ok(Signal::SigTerm));
+        assert_eq!(Signal::from_str("19"), Ok(Signal::SigStop));
+        assert!(Signal::from_str("sigfoo").is_err());
+        // SIGUSR1 has a different number on each platform.
+        assert!(Signal::from_str("10").is_err());
+    }
+
+    #[test]
+    fn test_kill_escalation_or() {
+        let mut task = test_helper::task(PUEUE_DEFAULT_GROUP, TaskStatus::Running);
+        let escalation = KillEscalation::default();
+        assert_eq!(task.kill_escalation_or(None), None);
+        assert_eq!(
+            task.kill_escalation_or(Some(&escalation)),
+            Some(&escalation)
+        );
+
+        let default = KillEscalation {
+            signal: Signal::SigInt,
+            grace_period: 5,
+            follow_up: Signal::SigTerm,
+        };
+        task.kill_escalation = Some(default.clone());
+        assert_eq!(task.kill_escalation_or(None), Some(&default));
+        assert_eq!(
+            task.kill_escalation_or(Some(&escalation)),
+            Some(&escalation)
+        );
+    }
 }
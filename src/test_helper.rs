@@ -0,0 +1,33 @@
+use crate::network::message::AddMessage;
+use crate::task::{Task, TaskStatus};
+
+/// A task in the given group, which lists the content of `/tmp`.
+pub fn task(group: &str, status: TaskStatus) -> Task {
+    Task::new(
+        "ls".into(),
+        "/tmp".into(),
+        Default::default(),
+        group.into(),
+        status,
+        Vec::new(),
+        None,
+    )
+}
+
+/// A request to add a task to the given group, which lists the content of `/tmp`.
+pub fn add_message(group: &str) -> AddMessage {
+    AddMessage {
+        command: "ls".into(),
+        path: "/tmp".into(),
+        envs: Default::default(),
+        start_immediately: false,
+        stashed: false,
+        group: group.into(),
+        enqueue_at: None,
+        dependencies: Vec::new(),
+        label: None,
+        print_task_id: false,
+        batch_dependencies: Vec::new(),
+        kill_escalation: None,
+    }
+}
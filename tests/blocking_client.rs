@@ -0,0 +1,96 @@
+#![cfg(all(feature = "async", feature = "blocking", not(target_os = "windows")))]
+use anyhow::Result;
+use pretty_assertions::assert_eq;
+use tokio::task;
+
+use pueue_lib::error::Error;
+use pueue_lib::network::blocking::client::Client;
+use pueue_lib::network::message::*;
+use pueue_lib::network::protocol::*;
+use pueue_lib::network::secret::init_shared_secret;
+
+mod helper;
+
+#[tokio::test]
+/// The blocking client speaks the same protocol as the async daemon.
+async fn test_blocking_client() -> Result<()> {
+    better_panic::install();
+    let (shared_settings, _tempdir) = helper::get_shared_settings();
+    init_shared_secret(&shared_settings.shared_secret_path())?;
+    let listener = get_listener(&shared_settings).await?;
+
+    // A daemon, which answers all requests on a single connection.
+    task::spawn(async move {
+        let mut stream = listener.accept().await.unwrap();
+        receive_bytes(&mut stream).await.unwrap();
+        send_bytes(b"2.0.0", &mut stream).await.unwrap();
+        let capabilities = daemon_handshake(&mut stream).await.unwrap();
+
+        loop {
+            let message = match receive_message_with(&capabilities, &mut stream).await {
+                Ok(message) => message,
+                Err(_) => break,
+            };
+            let responses = match message {
+                Message::Add(_) => vec![create_success_message("4")],
+                Message::Kill(_) => vec![create_structured_failure_message(
+                    ErrorCode::TaskNotRunning,
+                    vec![4],
+                    "Task 4 isn't running",
+                )],
+                Message::StreamRequest(_) => vec![
+                    Message::Stream("first\n".into()),
+                    create_success_message("Stream finished"),
+                ],
+                Message::Subscribe(_) => vec![
+                    create_success_message("Subscribed to events."),
+                    Message::Event(Event::GroupChanged {
+                        name: "default".into(),
+                        group: None,
+                    }),
+                    Message::Event(Event::DaemonShutdown(Shutdown::Graceful)),
+                ],
+                message => panic!("Unexpected message {:?}", message),
+            };
+            for response in responses {
+                send_message_with(response, &capabilities, &mut stream)
+                    .await
+                    .unwrap();
+            }
+        }
+    });
+
+    let add = helper::get_add_message("default");
+
+    task::spawn_blocking(move || -> Result<()> {
+        let mut client = Client::connect(&shared_settings)?;
+        assert_eq!(client.daemon_version(), "2.0.0");
+        assert_eq!(client.add(add)?, 4);
+
+        let result = client.kill(TaskSelection::TaskIds(vec![4]), None);
+        match result {
+            Err(Error::Failure(failure)) => assert_eq!(failure.code, ErrorCode::TaskNotRunning),
+            result => panic!("Expected failure, got {:?}", result),
+        }
+
+        let output = client
+            .stream_log(Some(4), false)?
+            .collect::<Result<Vec<String>, Error>>()?;
+        assert_eq!(output, vec!["first\n".to_string()]);
+
+        // The subscription ends with the daemon's shutdown.
+        let events = client
+            .subscribe(SubscribeMessage::default())?
+            .map(|event| event.map(|event| event.kind()))
+            .collect::<Result<Vec<EventKind>, Error>>()?;
+        assert_eq!(
+            events,
+            vec![EventKind::GroupChanged, EventKind::DaemonShutdown]
+        );
+
+        Ok(())
+    })
+    .await??;
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+#![cfg(all(feature = "async", not(target_os = "windows")))]
+use anyhow::Result;
+use pretty_assertions::assert_eq;
+use tokio::task;
+
+use pueue_lib::error::Error;
+use pueue_lib::network::client::Client;
+use pueue_lib::network::message::*;
+use pueue_lib::network::protocol::*;
+use pueue_lib::network::secret::init_shared_secret;
+use pueue_lib::settings::Settings;
+use pueue_lib::state::State;
+
+mod helper;
+
+#[tokio::test]
+/// The client authenticates, does the handshake and translates the daemon's responses.
+async fn test_client() -> Result<()> {
+    better_panic::install();
+    let (shared_settings, _tempdir) = helper::get_shared_settings();
+    init_shared_secret(&shared_settings.shared_secret_path())?;
+    let listener = get_listener(&shared_settings).await?;
+
+    // A daemon, which answers all requests on a single connection.
+    task::spawn(async move {
+        let mut stream = listener.accept().await.unwrap();
+        receive_bytes(&mut stream).await.unwrap();
+        send_bytes(b"2.0.0", &mut stream).await.unwrap();
+        let capabilities = daemon_handshake(&mut stream).await.unwrap();
+
+        loop {
+            let message = match receive_message_with(&capabilities, &mut stream).await {
+                Ok(message) => message,
+                Err(_) => break,
+            };
+            let responses = match message {
+                Message::Add(message) => {
+                    assert!(message.print_task_id);
+                    vec![create_success_message("4")]
+                }
+                Message::Status => {
+                    let settings = Settings::read_with_defaults(&None).unwrap().0;
+                    vec![Message::StatusResponse(Box::new(State::new(
+                        &settings, None,
+                    )))]
+                }
+                Message::Kill(_) => vec![create_structured_failure_message(
+                    ErrorCode::TaskNotFound,
+                    vec![9],
+                    "Task 9 doesn't exist",
+                )],
+                Message::StreamRequest(_) => vec![
+                    Message::Stream("first\n".into()),
+                    Message::Stream("second\n".into()),
+                    create_success_message("Stream finished"),
+                ],
+                message => panic!("Unexpected message {:?}", message),
+            };
+            for response in responses {
+                send_message_with(response, &capabilities, &mut stream)
+                    .await
+                    .unwrap();
+            }
+        }
+    });
+
+    let mut client = Client::connect(&shared_settings).await?;
+    assert_eq!(client.daemon_version(), "2.0.0");
+    assert_eq!(client.capabilities().version, PROTOCOL_VERSION);
+
+    assert_eq!(client.add(helper::get_add_message("default")).await?, 4);
+    assert!(client.status().await?.tasks.is_empty());
+
+    let result = client.kill(TaskSelection::TaskIds(vec![9]), None).await;
+    match result {
+        Err(Error::Failure(failure)) => {
+            assert_eq!(failure.code, ErrorCode::TaskNotFound);
+            assert_eq!(failure.task_ids, vec![9]);
+        }
+        result => panic!("Expected failure, got {:?}", result),
+    }
+
+    let mut stream = client.stream_log(Some(4), false).await?;
+    assert_eq!(stream.next().await?, Some("first\n".to_string()));
+    assert_eq!(stream.next().await?, Some("second\n".to_string()));
+    assert_eq!(stream.next().await?, None);
+
+    // The connection can still be used after the stream ended.
+    assert_eq!(client.add(helper::get_add_message("default")).await?, 4);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Daemons that predate the handshake are reported with their version.
+async fn test_client_legacy_daemon() -> Result<()> {
+    better_panic::install();
+    let (shared_settings, _tempdir) = helper::get_shared_settings();
+    init_shared_secret(&shared_settings.shared_secret_path())?;
+    let listener = get_listener(&shared_settings).await?;
+
+    // An old daemon, which doesn't understand the handshake and closes the connection.
+    task::spawn(async move {
+        let mut stream = listener.accept().await.unwrap();
+        receive_bytes(&mut stream).await.unwrap();
+        send_bytes(b"1.0.0", &mut stream).await.unwrap();
+        assert!(receive_message(&mut stream).await.is_err());
+    });
+
+    match Client::connect(&shared_settings).await {
+        Err(Error::MissingHandshake { remote_version }) => {
+            assert_eq!(remote_version.as_deref(), Some("1.0.0"))
+        }
+        result => panic!("Expected a missing handshake, got {:?}", result.err()),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,172 @@
+#![cfg(all(feature = "gateway", not(target_os = "windows")))]
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Result;
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use pretty_assertions::assert_eq;
+use serde_json::{json, Value};
+use snap::write::FrameEncoder;
+use tokio::task;
+use tower::ServiceExt;
+
+use pueue_lib::network::gateway::router;
+use pueue_lib::network::message::*;
+use pueue_lib::network::protocol::*;
+use pueue_lib::network::secret::{init_shared_secret, read_shared_secret};
+use pueue_lib::task::TaskStatus;
+
+mod helper;
+
+/// Send a request to the gateway and return the status and JSON body of the response.
+async fn call(router: &Router, request: Request<Body>) -> Result<(StatusCode, Value)> {
+    let response = router.clone().oneshot(request).await?;
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await?;
+
+    Ok((status, serde_json::from_slice(&body)?))
+}
+
+/// A daemon that answers each connection with the next response.
+/// It checks that each incoming request matches the expected request.
+fn spawn_daemon(listener: GenericListener, secret: Vec<u8>, exchanges: Vec<(Message, Message)>) {
+    task::spawn(async move {
+        for (expected, response) in exchanges {
+            let mut stream = listener.accept().await.unwrap();
+            assert_eq!(receive_bytes(&mut stream).await.unwrap(), secret);
+            send_bytes(b"2.0.0", &mut stream).await.unwrap();
+            let capabilities = daemon_handshake(&mut stream).await.unwrap();
+
+            let message = receive_message_with(&capabilities, &mut stream)
+                .await
+                .unwrap();
+            assert_eq!(message, expected);
+            send_message_with(response, &capabilities, &mut stream)
+                .await
+                .unwrap();
+        }
+    });
+}
+
+#[tokio::test]
+/// The REST endpoints are translated to messages and the daemon's responses to JSON.
+async fn test_gateway() -> Result<()> {
+    better_panic::install();
+    let (shared_settings, _tempdir) = helper::get_shared_settings();
+    init_shared_secret(&shared_settings.shared_secret_path())?;
+    let secret = read_shared_secret(&shared_settings.shared_secret_path())?;
+    let bearer = format!("Bearer {}", String::from_utf8(secret.clone())?);
+
+    let task = helper::get_task("default", TaskStatus::Running);
+    let mut encoder = FrameEncoder::new(Vec::new());
+    encoder.write_all(b"some output")?;
+    let mut logs = BTreeMap::new();
+    logs.insert(
+        3,
+        TaskLogMessage {
+            task: task.clone(),
+            stdout: Some(encoder.into_inner()?),
+            stderr: None,
+        },
+    );
+
+    let exchanges = vec![
+        (
+            Message::Add(AddMessage {
+                print_task_id: true,
+                ..helper::get_add_message("default")
+            }),
+            create_success_message("3"),
+        ),
+        (
+            Message::Kill(KillMessage {
+                tasks: TaskSelection::TaskIds(vec![3]),
+                children: false,
+                signal: Some(Signal::SigHup),
+                process_group: true,
+                escalation: None,
+            }),
+            create_structured_failure_message(
+                ErrorCode::TaskNotRunning,
+                vec![3],
+                "Task 3 isn't running",
+            ),
+        ),
+        (
+            Message::Kill(KillMessage {
+                tasks: TaskSelection::TaskIds(vec![3]),
+                children: false,
+                signal: Some(Signal::SigTerm),
+                process_group: false,
+                escalation: Some(KillEscalation {
+                    signal: Signal::SigTerm,
+                    grace_period: 5,
+                    follow_up: Signal::SigKill,
+                }),
+            }),
+            create_success_message("Tasks are being killed: 3"),
+        ),
+        (
+            Message::Log(LogRequestMessage {
+                task_ids: vec![3],
+                send_logs: true,
+                lines: Some(10),
+            }),
+            Message::LogResponse(logs),
+        ),
+    ];
+    let listener = get_listener(&shared_settings).await?;
+    spawn_daemon(listener, secret, exchanges);
+
+    let router = router(shared_settings)?;
+
+    // Requests without the secret are rejected before the daemon is contacted.
+    let request = Request::get("/tasks").body(Body::empty())?;
+    let (status, _) = call(&router, request).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let request = Request::post("/tasks")
+        .header(header::AUTHORIZATION, &bearer)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"command": "ls", "path": "/tmp"}"#))?;
+    let (status, body) = call(&router, request).await?;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body, json!({"task_id": 3}));
+
+    let request = Request::post("/tasks/3/kill?signal=sighup&process_group=true")
+        .header(header::AUTHORIZATION, &bearer)
+        .body(Body::empty())?;
+    let (status, body) = call(&router, request).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body,
+        json!({"error": "Task 3 isn't running", "code": "TaskNotRunning", "task_ids": [3]})
+    );
+
+    // Signals can be given by their number as well.
+    let request = Request::post("/tasks/3/kill?signal=15&grace_period=5&follow_up=SIGKILL")
+        .header(header::AUTHORIZATION, &bearer)
+        .body(Body::empty())?;
+    let (status, body) = call(&router, request).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({"message": "Tasks are being killed: 3"}));
+
+    let request = Request::post("/tasks/3/kill?signal=sigfoo")
+        .header(header::AUTHORIZATION, &bearer)
+        .body(Body::empty())?;
+    let response = router.clone().oneshot(request).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let request = Request::get("/tasks/3/log?lines=10")
+        .header(header::AUTHORIZATION, &bearer)
+        .body(Body::empty())?;
+    let (status, body) = call(&router, request).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["stdout"], json!("some output"));
+    assert_eq!(body["stderr"], Value::Null);
+    assert_eq!(body["task"]["command"], json!("ls"));
+
+    Ok(())
+}
@@ -1,7 +1,11 @@
+// Not every test uses all of the fixtures.
+#![allow(dead_code)]
 use tempdir::TempDir;
 
 use portpicker::pick_unused_port;
+use pueue_lib::network::message::AddMessage;
 use pueue_lib::settings::*;
+use pueue_lib::task::{Task, TaskStatus};
 
 pub fn get_shared_settings() -> (Shared, TempDir) {
     // Create a temporary directory used for testing.
@@ -11,7 +15,7 @@ pub fn get_shared_settings() -> (Shared, TempDir) {
     std::fs::create_dir(tempdir_path.join("certs")).unwrap();
 
     let shared_settings = Shared {
-        pueue_directory: tempdir_path.clone().to_path_buf(),
+        pueue_directory: tempdir_path.to_path_buf(),
         #[cfg(not(target_os = "windows"))]
         use_unix_socket: true,
         #[cfg(not(target_os = "windows"))]
@@ -27,3 +31,34 @@ pub fn get_shared_settings() -> (Shared, TempDir) {
 
     (shared_settings, tempdir)
 }
+
+/// A task in the given group, which lists the content of `/tmp`.
+pub fn get_task(group: &str, status: TaskStatus) -> Task {
+    Task::new(
+        "ls".into(),
+        "/tmp".into(),
+        Default::default(),
+        group.into(),
+        status,
+        Vec::new(),
+        None,
+    )
+}
+
+/// A request to add a task to the given group, which lists the content of `/tmp`.
+pub fn get_add_message(group: &str) -> AddMessage {
+    AddMessage {
+        command: "ls".into(),
+        path: "/tmp".into(),
+        envs: Default::default(),
+        start_immediately: false,
+        stashed: false,
+        group: group.into(),
+        enqueue_at: None,
+        dependencies: Vec::new(),
+        label: None,
+        print_task_id: false,
+        batch_dependencies: Vec::new(),
+        kill_escalation: None,
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::{BTreeMap, HashMap};
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::prelude::*;
+use pretty_assertions::assert_eq;
+use serde_json::Value;
+
+use pueue_lib::archive::ArchivedTask;
+use pueue_lib::network::message::*;
+use pueue_lib::network::protocol::{deserialize, serialize, WireFormat};
+use pueue_lib::state::{Group, GroupStatus, QueueOrdering, State, TimeWindow};
+use pueue_lib::task::{Task, TaskResult, TaskStatus};
+
+/// The JSON representation of all messages is part of our public API, as clients in other
+/// languages rely on it. For this reason, an example of every message has been checked in.
+///
+/// If this test fails, the JSON representation of a message has changed.
+/// That's only fine for newly added messages or fields, which then have to be added to the file.
+#[test]
+fn test_json_messages() -> Result<()> {
+    better_panic::install();
+    let path = data_path("json_messages.json");
+    let data = fs::read_to_string(&path).context("Failed to read json messages")?;
+
+    let expected: Vec<Value> = serde_json::from_str(&data)?;
+    let messages = get_messages()?;
+    assert_eq!(expected.len(), messages.len());
+
+    for (expected, message) in expected.into_iter().zip(messages) {
+        // The documented representation is understood by us.
+        let parsed: Message = deserialize(expected.to_string().as_bytes(), WireFormat::Json)?;
+        assert_eq!(parsed, message);
+
+        // We produce exactly the documented representation.
+        let payload = serialize(&message, WireFormat::Json)?;
+        let actual: Value = serde_json::from_slice(&payload)?;
+        assert_eq!(normalize_datetimes(actual), normalize_datetimes(expected));
+    }
+
+    Ok(())
+}
+
+fn data_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("data")
+        .join(name)
+}
+
+/// Datetimes are serialized with the local offset, which depends on the machine.
+/// Convert them to UTC, so they can be compared.
+fn normalize_datetimes(value: Value) -> Value {
+    match value {
+        Value::String(text) => match DateTime::parse_from_rfc3339(&text) {
+            Ok(datetime) => Value::String(datetime.with_timezone(&Utc).to_rfc3339()),
+            Err(_) => Value::String(text),
+        },
+        Value::Array(values) => Value::Array(values.into_iter().map(normalize_datetimes).collect()),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, normalize_datetimes(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn datetime() -> DateTime<Local> {
+    Utc.with_ymd_and_hms(2022, 1, 1, 12, 0, 0)
+        .unwrap()
+        .with_timezone(&Local)
+}
+
+fn get_task() -> Task {
+    let mut envs = HashMap::new();
+    envs.insert("PUEUE_WORKER_ID".to_string(), "0".to_string());
+    let mut task = Task::new(
+        "sleep 60".into(),
+        "/tmp".into(),
+        envs,
+        "default".into(),
+        TaskStatus::Done(TaskResult::Failed(1)),
+        vec![0],
+        Some("sleepy".into()),
+    );
+    task.id = 1;
+    task.prev_status = TaskStatus::Running;
+    task.start = Some(datetime());
+    task.end = Some(datetime());
+    task.kill_escalation = Some(KillEscalation::default());
+
+    task
+}
+
+fn get_group() -> Group {
+    Group {
+        status: GroupStatus::Paused,
+        parallel_tasks: 2,
+        time_windows: vec![get_time_window()],
+        resume_at: Some(datetime()),
+        ordering: QueueOrdering::ShortestFirst,
+        parent: Some("default".into()),
+    }
+}
+
+fn get_time_window() -> TimeWindow {
+    TimeWindow {
+        weekdays: vec![Weekday::Mon, Weekday::Fri],
+        start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+        end: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+    }
+}
+
+fn get_add_message() -> AddMessage {
+    let mut envs = HashMap::new();
+    envs.insert("PUEUE_WORKER_ID".to_string(), "0".to_string());
+    AddMessage {
+        command: "sleep 60".into(),
+        path: "/tmp".into(),
+        envs,
+        start_immediately: false,
+        stashed: false,
+        group: "default".into(),
+        enqueue_at: Some(datetime()),
+        dependencies: vec![0],
+        label: Some("sleepy".into()),
+        print_task_id: false,
+        batch_dependencies: Vec::new(),
+        kill_escalation: Some(KillEscalation::default()),
+    }
+}
+
+/// An example of every message, in the same order as in the json file.
+fn get_messages() -> Result<Vec<Message>> {
+    let data = fs::read_to_string(data_path("v0.18.0_state.json"))?;
+    let state: State = serde_json::from_str(&data)?;
+    // The old state still contains some legacy fields, which are no longer serialized.
+    let state: State = serde_json::from_value(serde_json::to_value(state)?)?;
+
+    let mut groups = BTreeMap::new();
+    groups.insert("test".to_string(), get_group());
+
+    let mut logs = BTreeMap::new();
+    logs.insert(
+        1,
+        TaskLogMessage {
+            task: get_task(),
+            stdout: Some(vec![112, 117, 101, 117, 101]),
+            stderr: None,
+        },
+    );
+
+    let mut envs = HashMap::new();
+    envs.insert("PUEUE_WORKER_ID".to_string(), "0".to_string());
+
+    let mut batch_add = get_add_message();
+    batch_add.dependencies = Vec::new();
+    batch_add.batch_dependencies = vec![0];
+
+    let messages = vec![
+        Message::Add(get_add_message()),
+        Message::Remove(vec![0, 1]),
+        Message::Switch(SwitchMessage {
+            task_id_1: 0,
+            task_id_2: 1,
+        }),
+        Message::Stash(vec![0]),
+        Message::Enqueue(EnqueueMessage {
+            task_ids: vec![0],
+            enqueue_at: Some(datetime()),
+        }),
+        Message::MoveToGroup(MoveToGroupMessage {
+            task_ids: vec![0, 1],
+            group: "test".into(),
+        }),
+        Message::Start(StartMessage {
+            tasks: TaskSelection::TaskIds(vec![0]),
+            children: false,
+        }),
+        Message::Restart(RestartMessage {
+            tasks: vec![TasksToRestart {
+                task_id: 0,
+                command: "sleep 60".into(),
+                path: "/tmp".into(),
+            }],
+            start_immediately: true,
+            stashed: false,
+        }),
+        Message::Pause(PauseMessage {
+            tasks: TaskSelection::Group("test".into()),
+            wait: true,
+            children: false,
+            resume_at: Some(datetime()),
+        }),
+        Message::Kill(KillMessage {
+            tasks: TaskSelection::All,
+            children: true,
+            signal: Some(Signal::SigUsr1),
+            process_group: true,
+            escalation: None,
+        }),
+        Message::Kill(KillMessage {
+            tasks: TaskSelection::TaskIds(vec![0]),
+            children: false,
+            signal: None,
+            process_group: false,
+            escalation: Some(KillEscalation {
+                signal: Signal::SigInt,
+                grace_period: 30,
+                follow_up: Signal::SigTerm,
+            }),
+        }),
+        Message::Send(SendMessage {
+            task_id: 0,
+            input: "y\n".into(),
+        }),
+        Message::EditRequest(0),
+        Message::EditResponse(EditResponseMessage {
+            task_id: 0,
+            command: "sleep 60".into(),
+            path: "/tmp".into(),
+            label: Some("sleepy".into()),
+            envs: envs.clone(),
+            group: "default".into(),
+            dependencies: Vec::new(),
+            enqueue_at: None,
+        }),
+        Message::Edit(EditMessage {
+            task_id: 0,
+            command: "sleep 30".into(),
+            path: "/tmp".into(),
+            label: None,
+            envs,
+            group: "test".into(),
+            dependencies: vec![1],
+            enqueue_at: Some(datetime()),
+        }),
+        Message::PartialEdit(PartialEditMessage {
+            task_id: 0,
+            changes: vec![
+                TaskChange::Label(None),
+                TaskChange::Group("test".into()),
+                TaskChange::EnqueueAt(Some(datetime())),
+            ],
+        }),
+        Message::Group(GroupMessage::Add {
+            name: "test".into(),
+            parallel_tasks: Some(2),
+            time_windows: Some(vec![get_time_window()]),
+            ordering: Some(QueueOrdering::Lifo),
+            parent: Some("default".into()),
+        }),
+        Message::Group(GroupMessage::Edit {
+            name: "test".into(),
+            time_windows: None,
+            ordering: Some(QueueOrdering::Fifo),
+        }),
+        Message::Group(GroupMessage::SetParent {
+            name: "test".into(),
+            parent: None,
+        }),
+        Message::Group(GroupMessage::Rename {
+            name: "test".into(),
+            new_name: "renamed".into(),
+        }),
+        Message::Group(GroupMessage::Remove("test".into())),
+        Message::Group(GroupMessage::List),
+        Message::GroupResponse(GroupResponseMessage {
+            groups: groups.clone(),
+        }),
+        Message::Env(EnvMessage::Set {
+            tasks: TaskSelection::Group("test".into()),
+            key: "TOKEN".into(),
+            value: "secret".into(),
+        }),
+        Message::Env(EnvMessage::Unset {
+            tasks: TaskSelection::All,
+            key: "TOKEN".into(),
+        }),
+        Message::Env(EnvMessage::List(TaskSelection::TaskIds(vec![0, 1]))),
+        Message::EnvResponse(EnvResponseMessage {
+            envs: BTreeMap::from([(0, HashMap::from([("TOKEN".into(), "secret".into())]))]),
+        }),
+        Message::Status,
+        Message::StatusResponse(Box::new(state)),
+        Message::Log(LogRequestMessage {
+            task_ids: vec![1],
+            send_logs: true,
+            lines: Some(10),
+        }),
+        Message::LogResponse(logs),
+        Message::LogChunk(LogChunkMessage {
+            task_id: 1,
+            task: None,
+            stdout: Some(vec![112, 117, 101, 117, 101]),
+            stderr: None,
+            last: true,
+        }),
+        Message::StreamRequest(StreamRequestMessage {
+            task_id: Some(1),
+            err: false,
+        }),
+        Message::Stream("some output\n".into()),
+        Message::MultiStreamRequest(MultiStreamRequestMessage {
+            task_ids: vec![1],
+            kinds: vec![OutputKind::Stdout, OutputKind::Stderr],
+            offsets: vec![StreamOffset {
+                task_id: 1,
+                kind: OutputKind::Stdout,
+                offset: 5,
+            }],
+        }),
+        Message::StreamChunk(StreamChunkMessage {
+            task_id: 1,
+            kind: OutputKind::Stdout,
+            offset: 5,
+            data: vec![10],
+            last: true,
+        }),
+        Message::Wait(WaitMessage {
+            tasks: TaskSelection::TaskIds(vec![1]),
+            condition: WaitCondition::Succeeded,
+            timeout: Some(60),
+        }),
+        Message::WaitUpdate(WaitUpdateMessage {
+            task_id: 1,
+            status: TaskStatus::Done(TaskResult::Failed(1)),
+            reached: false,
+        }),
+        Message::Subscribe(SubscribeMessage {
+            task_ids: vec![1],
+            groups: vec!["default".into()],
+            kinds: vec![EventKind::TaskStatusChanged, EventKind::GroupChanged],
+        }),
+        Message::Event(Event::TaskAdded(get_task())),
+        Message::Event(Event::TaskStatusChanged {
+            task_id: 1,
+            group: "default".into(),
+            previous_status: TaskStatus::Running,
+            status: TaskStatus::Done(TaskResult::Success),
+        }),
+        Message::Event(Event::TaskRemoved {
+            task_id: 1,
+            group: "default".into(),
+        }),
+        Message::Event(Event::GroupChanged {
+            name: "test".into(),
+            group: Some(get_group()),
+        }),
+        Message::Event(Event::DaemonShutdown(Shutdown::Graceful)),
+        Message::Reset(ResetMessage { children: true }),
+        Message::Clean(CleanMessage {
+            successful_only: false,
+            group: Some("default".into()),
+            archive: true,
+            archive_logs: false,
+        }),
+        Message::ArchiveQuery(ArchiveQueryMessage {
+            task_ids: Vec::new(),
+            group: None,
+            failed_only: true,
+            limit: Some(10),
+            send_logs: false,
+        }),
+        Message::ArchiveResponse(vec![ArchivedTask {
+            archive_id: 0,
+            archived_at: datetime(),
+            task: get_task(),
+            stdout: None,
+            stderr: None,
+            log_error: None,
+        }]),
+        Message::ArchiveRestore(vec![0]),
+        Message::DaemonShutdown(Shutdown::Emergency),
+        Message::Success("Tasks have been removed".into()),
+        Message::Failure("Task 5 doesn't exist".into()),
+        Message::StructuredFailure(FailureMessage {
+            code: ErrorCode::TaskNotFound,
+            task_ids: vec![5],
+            text: "Task 5 doesn't exist".into(),
+        }),
+        Message::PartialSuccess(PartialSuccessMessage {
+            succeeded: vec![0, 1, 2],
+            failures: vec![FailureMessage {
+                code: ErrorCode::TaskRunning,
+                task_ids: vec![3, 4],
+                text: "Tasks are already running: 3, 4".into(),
+            }],
+            text: "Tasks are being started: 0, 1, 2\nTasks are already running: 3, 4".into(),
+        }),
+        Message::Parallel(ParallelMessage {
+            parallel_tasks: 4,
+            group: "default".into(),
+        }),
+        Message::Batch(BatchMessage {
+            messages: vec![Message::Add(get_add_message()), Message::Add(batch_add)],
+            atomic: true,
+        }),
+        Message::BatchResponse(vec![
+            Message::Success("New task added (id 2).".into()),
+            Message::Failure("Batch item 0 didn't create a task.".into()),
+        ]),
+    ];
+
+    Ok(messages)
+}
@@ -0,0 +1,666 @@
+#![cfg(all(feature = "mock", not(target_os = "windows")))]
+use std::io::Read;
+
+use anyhow::Result;
+use chrono::{Duration, Local};
+use pretty_assertions::assert_eq;
+use snap::read::FrameDecoder;
+
+use pueue_lib::error::Error;
+use pueue_lib::network::client::Client;
+use pueue_lib::network::message::*;
+use pueue_lib::network::mock::MockDaemon;
+use pueue_lib::task::{TaskResult, TaskStatus};
+
+mod helper;
+
+fn failure_code(result: Result<Message, Error>) -> ErrorCode {
+    match result {
+        Err(Error::Failure(failure)) => failure.code,
+        result => panic!("Expected failure, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+/// The mock daemon answers requests from its in-memory state and follows scripted transitions.
+async fn test_mock_daemon() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+
+    assert_eq!(client.add(helper::get_add_message("default")).await?, 0);
+    assert_eq!(client.add(helper::get_add_message("default")).await?, 1);
+    assert!(matches!(
+        client.add(helper::get_add_message("unknown")).await,
+        Err(Error::Failure(FailureMessage {
+            code: ErrorCode::GroupNotFound,
+            ..
+        }))
+    ));
+
+    // Script the lifecycle of the tasks.
+    daemon.set_status(0, TaskStatus::Running)?;
+    daemon.set_status(1, TaskStatus::Done(TaskResult::Failed(1)))?;
+    daemon.set_output(1, "first\nsecond\n", "")?;
+
+    let state = client.status().await?;
+    assert_eq!(state.tasks[&0].status, TaskStatus::Running);
+    assert_eq!(
+        state.tasks[&1].status,
+        TaskStatus::Done(TaskResult::Failed(1))
+    );
+    assert!(state.tasks[&1].end.is_some());
+
+    // Only the running task can be killed.
+    let response = client
+        .send_request(Message::Kill(KillMessage {
+            tasks: TaskSelection::All,
+            children: false,
+            signal: None,
+            process_group: false,
+            escalation: None,
+        }))
+        .await?;
+    match response {
+        Message::PartialSuccess(result) => {
+            assert_eq!(result.succeeded, vec![0]);
+            assert_eq!(result.failed_task_ids(), vec![1]);
+        }
+        response => panic!("Expected partial success, got {:?}", response),
+    }
+    assert_eq!(
+        daemon.state().tasks[&0].status,
+        TaskStatus::Done(TaskResult::Killed)
+    );
+
+    let logs = client.logs(vec![1], Some(1)).await?;
+    let mut stdout = String::new();
+    FrameDecoder::new(logs[&1].stdout.as_deref().unwrap()).read_to_string(&mut stdout)?;
+    assert_eq!(stdout, "second\n");
+
+    client
+        .send_request(Message::Group(GroupMessage::Add {
+            name: "test".into(),
+            parallel_tasks: Some(2),
+            time_windows: None,
+            ordering: None,
+            parent: None,
+        }))
+        .await?;
+    match client
+        .send_request(Message::Group(GroupMessage::List))
+        .await?
+    {
+        Message::GroupResponse(response) => {
+            assert_eq!(response.groups["test"].parallel_tasks, 2);
+        }
+        response => panic!("Expected group response, got {:?}", response),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+/// The daemon answers a wait request as soon as tasks reach the condition.
+async fn test_mock_daemon_wait() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+    for _ in 0..3 {
+        client.add(helper::get_add_message("default")).await?;
+    }
+    daemon.set_status(0, TaskStatus::Done(TaskResult::Success))?;
+
+    let mut wait = client
+        .wait(TaskSelection::All, WaitCondition::Succeeded, Some(1))
+        .await?;
+    let update = wait.next().await?.unwrap();
+    assert_eq!((update.task_id, update.reached), (0, true));
+
+    // The remaining tasks finish while we're waiting.
+    daemon.set_status(1, TaskStatus::Done(TaskResult::Failed(1)))?;
+    let update = wait.next().await?.unwrap();
+    assert_eq!(update.task_id, 1);
+    assert_eq!(update.status, TaskStatus::Done(TaskResult::Failed(1)));
+    assert!(!update.reached);
+
+    // Task 2 never finishes.
+    assert!(wait.next().await?.is_none());
+    let codes: Vec<(ErrorCode, Vec<usize>)> = wait
+        .failures()
+        .iter()
+        .map(|failure| (failure.code, failure.task_ids.clone()))
+        .collect();
+    assert_eq!(
+        codes,
+        vec![
+            (ErrorCode::ConditionUnreachable, vec![1]),
+            (ErrorCode::Timeout, vec![2]),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Output of several tasks is streamed and can be resumed on a new connection.
+async fn test_mock_daemon_stream_outputs() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+    client.add(helper::get_add_message("default")).await?;
+    client.add(helper::get_add_message("default")).await?;
+    daemon.set_status(0, TaskStatus::Running)?;
+    daemon.set_status(1, TaskStatus::Running)?;
+    daemon.set_output(0, "first\n", "")?;
+
+    let request = MultiStreamRequestMessage {
+        kinds: vec![OutputKind::Stdout],
+        ..Default::default()
+    };
+    let mut stream = client.stream_outputs(request).await?;
+    let chunk = stream.next().await?.unwrap();
+    assert_eq!((chunk.task_id, chunk.offset), (0, 0));
+    assert_eq!(chunk.data, b"first\n".to_vec());
+
+    // The connection went away. Output that's written meanwhile isn't lost.
+    let request = stream.request().clone();
+    drop(client);
+    daemon.set_output(0, "first\nsecond\n", "")?;
+    daemon.set_status(1, TaskStatus::Done(TaskResult::Success))?;
+
+    let mut client = Client::connect(daemon.settings()).await?;
+    let mut stream = client.stream_outputs(request).await?;
+    let mut chunks = Vec::new();
+    while let Some(chunk) = stream.next().await? {
+        chunks.push(chunk);
+        if chunks.len() == 2 {
+            daemon.set_status(0, TaskStatus::Done(TaskResult::Success))?;
+        }
+    }
+
+    let chunks: Vec<(usize, u64, Vec<u8>, bool)> = chunks
+        .into_iter()
+        .map(|chunk| (chunk.task_id, chunk.offset, chunk.data, chunk.last))
+        .collect();
+    assert_eq!(
+        chunks,
+        vec![
+            (0, 6, b"second\n".to_vec(), false),
+            (1, 0, Vec::new(), true),
+            (0, 13, Vec::new(), true),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Following all tasks includes tasks that are added while streaming.
+async fn test_mock_daemon_stream_new_tasks() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+    client.add(helper::get_add_message("default")).await?;
+    daemon.set_status(0, TaskStatus::Running)?;
+
+    let mut follower = Client::connect(daemon.settings()).await?;
+    let request = MultiStreamRequestMessage {
+        kinds: vec![OutputKind::Stdout],
+        ..Default::default()
+    };
+    let mut stream = follower.stream_outputs(request).await?;
+    daemon.set_output(0, "first\n", "")?;
+    let chunk = stream.next().await?.unwrap();
+    assert_eq!((chunk.task_id, chunk.data), (0, b"first\n".to_vec()));
+
+    client.add(helper::get_add_message("default")).await?;
+    daemon.set_output(1, "new\n", "")?;
+    let chunk = stream.next().await?.unwrap();
+    assert_eq!((chunk.task_id, chunk.data), (1, b"new\n".to_vec()));
+
+    daemon.set_status(0, TaskStatus::Done(TaskResult::Success))?;
+    daemon.set_status(1, TaskStatus::Done(TaskResult::Success))?;
+    let mut finished = Vec::new();
+    while let Some(chunk) = stream.next().await? {
+        assert!(chunk.last);
+        finished.push(chunk.task_id);
+    }
+    finished.sort_unstable();
+    assert_eq!(finished, vec![0, 1]);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Tasks can be edited with the locking edit protocol and with partial edits.
+async fn test_mock_daemon_edit() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+    client.add(helper::get_add_message("default")).await?;
+    client.add(helper::get_add_message("default")).await?;
+    client
+        .send_request(Message::Group(GroupMessage::Add {
+            name: "test".into(),
+            parallel_tasks: None,
+            time_windows: None,
+            ordering: None,
+            parent: None,
+        }))
+        .await?;
+
+    // The task is locked while it's being edited.
+    let mut edit = match client.send_request(Message::EditRequest(1)).await? {
+        Message::EditResponse(response) => response,
+        response => panic!("Expected edit response, got {:?}", response),
+    };
+    assert_eq!(daemon.state().tasks[&1].status, TaskStatus::Locked);
+    edit.command = "ls -al".into();
+    edit.label = Some("edited".into());
+    edit.group = "test".into();
+    edit.dependencies = vec![0];
+    client
+        .send_request(Message::Edit(EditMessage {
+            task_id: edit.task_id,
+            command: edit.command,
+            path: edit.path,
+            label: edit.label,
+            envs: edit.envs,
+            group: edit.group,
+            dependencies: edit.dependencies,
+            enqueue_at: edit.enqueue_at,
+        }))
+        .await?;
+
+    let state = daemon.state();
+    let task = &state.tasks[&1];
+    assert_eq!(task.status, TaskStatus::Queued);
+    assert_eq!(task.original_command, "ls -al");
+    assert_eq!(task.label.as_deref(), Some("edited"));
+    assert_eq!(task.group, "test");
+    assert_eq!(task.dependencies, vec![0]);
+
+    // The enqueue time of stashed tasks can be removed.
+    let mut stashed = helper::get_add_message("default");
+    stashed.enqueue_at = Some(Local::now() + Duration::hours(1));
+    let task_id = client.add(stashed).await?;
+    let edit = match client.send_request(Message::EditRequest(task_id)).await? {
+        Message::EditResponse(response) => response,
+        response => panic!("Expected edit response, got {:?}", response),
+    };
+    assert!(edit.enqueue_at.is_some());
+    client
+        .send_request(Message::Edit(EditMessage {
+            task_id,
+            command: edit.command,
+            path: edit.path,
+            label: edit.label,
+            envs: edit.envs,
+            group: edit.group,
+            dependencies: edit.dependencies,
+            enqueue_at: None,
+        }))
+        .await?;
+    assert_eq!(
+        daemon.state().tasks[&task_id].status,
+        TaskStatus::Stashed { enqueue_at: None }
+    );
+
+    // Partial edits are applied completely or not at all.
+    let result = client
+        .edit(
+            0,
+            vec![
+                TaskChange::Label(Some("cycle".into())),
+                TaskChange::Dependencies(vec![1]),
+            ],
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(Error::Failure(FailureMessage {
+            code: ErrorCode::InvalidDependency,
+            ..
+        }))
+    ));
+    assert_eq!(daemon.state().tasks[&0].label, None);
+
+    client
+        .edit(0, vec![TaskChange::Label(Some("first".into()))])
+        .await?;
+    assert_eq!(daemon.state().tasks[&0].label.as_deref(), Some("first"));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Environment variables of all non-running tasks can be changed at once.
+async fn test_mock_daemon_env() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+    for _ in 0..3 {
+        client.add(helper::get_add_message("default")).await?;
+    }
+    daemon.set_status(2, TaskStatus::Running)?;
+
+    client
+        .set_env(TaskSelection::Group("default".into()), "TOKEN", "fresh")
+        .await?;
+    client
+        .unset_env(TaskSelection::TaskIds(vec![1]), "TOKEN")
+        .await?;
+
+    let envs = client.envs(TaskSelection::All).await?;
+    assert_eq!(envs[&0].get("TOKEN").map(String::as_str), Some("fresh"));
+    assert_eq!(envs[&1].get("TOKEN"), None);
+    assert_eq!(envs[&2].get("TOKEN"), None);
+
+    // The running task is reported as such.
+    let response = client
+        .send_request(Message::Env(EnvMessage::Set {
+            tasks: TaskSelection::All,
+            key: "TOKEN".into(),
+            value: "fresh".into(),
+        }))
+        .await?;
+    match response {
+        Message::PartialSuccess(result) => {
+            assert_eq!(result.succeeded, vec![0, 1]);
+            assert_eq!(result.failures[0].code, ErrorCode::TaskRunning);
+            assert_eq!(result.failed_task_ids(), vec![2]);
+        }
+        response => panic!("Expected partial success, got {:?}", response),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Tasks keep their default kill escalation and can be killed with an explicit one.
+async fn test_mock_daemon_kill_escalation() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+    let escalation = KillEscalation {
+        signal: Signal::SigInt,
+        grace_period: 5,
+        follow_up: Signal::SigTerm,
+    };
+    let mut message = helper::get_add_message("default");
+    message.kill_escalation = Some(escalation.clone());
+    client.add(message).await?;
+    assert_eq!(
+        daemon.state().tasks[&0].kill_escalation,
+        Some(escalation.clone())
+    );
+
+    client.add(helper::get_add_message("default")).await?;
+    client.add(helper::get_add_message("default")).await?;
+    for task_id in 0..3 {
+        daemon.set_status(task_id, TaskStatus::Running)?;
+    }
+
+    // Without an escalation in the request, the task's default is used.
+    client.kill(TaskSelection::TaskIds(vec![0]), None).await?;
+    let kill = daemon.last_kill(0).unwrap();
+    assert_eq!(kill.escalation, Some(escalation));
+    assert_eq!(kill.signal, None);
+    assert_eq!(
+        daemon.state().tasks[&0].status,
+        TaskStatus::Done(TaskResult::Killed)
+    );
+
+    client
+        .kill_with_escalation(TaskSelection::TaskIds(vec![1]), KillEscalation::default())
+        .await?;
+    assert_eq!(
+        daemon.last_kill(1).unwrap().escalation,
+        Some(KillEscalation::default())
+    );
+
+    // An explicit signal is sent right away.
+    client
+        .send_request(Message::Kill(KillMessage {
+            tasks: TaskSelection::TaskIds(vec![2]),
+            children: false,
+            signal: Some(Signal::SigHup),
+            process_group: true,
+            escalation: None,
+        }))
+        .await?;
+    let kill = daemon.last_kill(2).unwrap();
+    assert_eq!(kill.escalation, None);
+    assert_eq!(kill.signal, Some(Signal::SigHup));
+    assert!(kill.process_group);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Groups can be renamed and tasks that aren't running can be moved between groups.
+async fn test_mock_daemon_move_to_group() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+    client
+        .send_request(Message::Group(GroupMessage::Add {
+            name: "old".into(),
+            parallel_tasks: None,
+            time_windows: None,
+            ordering: None,
+            parent: None,
+        }))
+        .await?;
+    for _ in 0..2 {
+        client.add(helper::get_add_message("old")).await?;
+    }
+    daemon.set_status(1, TaskStatus::Running)?;
+
+    let rename = |name: &str, new_name: &str| {
+        Message::Group(GroupMessage::Rename {
+            name: name.into(),
+            new_name: new_name.into(),
+        })
+    };
+    assert_eq!(
+        failure_code(client.send_request(rename("missing", "new")).await),
+        ErrorCode::GroupNotFound
+    );
+    assert_eq!(
+        failure_code(client.send_request(rename("old", "default")).await),
+        ErrorCode::GroupExists
+    );
+    client.send_request(rename("old", "new")).await?;
+    assert_eq!(daemon.state().tasks[&0].group, "new");
+
+    let response = client
+        .send_request(Message::MoveToGroup(MoveToGroupMessage {
+            task_ids: vec![0, 1, 2],
+            group: "default".into(),
+        }))
+        .await?;
+    match response {
+        Message::PartialSuccess(result) => {
+            assert_eq!(result.succeeded, vec![0]);
+            assert_eq!(result.failures[0].code, ErrorCode::TaskRunning);
+            assert_eq!(result.failures[1].code, ErrorCode::TaskNotFound);
+            assert_eq!(result.failed_task_ids(), vec![1, 2]);
+        }
+        response => panic!("Expected partial success, got {:?}", response),
+    }
+    assert_eq!(daemon.state().tasks[&0].group, "default");
+    assert_eq!(daemon.state().tasks[&1].group, "new");
+
+    let response = client
+        .send_request(Message::MoveToGroup(MoveToGroupMessage {
+            task_ids: vec![0],
+            group: "old".into(),
+        }))
+        .await;
+    assert_eq!(failure_code(response), ErrorCode::GroupNotFound);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Groups can't become children of their own subgroups.
+async fn test_mock_daemon_group_parent() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+    client
+        .send_request(Message::Group(GroupMessage::Add {
+            name: "project".into(),
+            parallel_tasks: None,
+            time_windows: None,
+            ordering: None,
+            parent: Some("default".into()),
+        }))
+        .await?;
+
+    let set_parent = |name: &str, parent: &str| {
+        Message::Group(GroupMessage::SetParent {
+            name: name.into(),
+            parent: Some(parent.into()),
+        })
+    };
+    assert_eq!(
+        failure_code(client.send_request(set_parent("default", "project")).await),
+        ErrorCode::InvalidRequest
+    );
+    assert_eq!(
+        failure_code(client.send_request(set_parent("project", "missing")).await),
+        ErrorCode::GroupNotFound
+    );
+    assert_eq!(daemon.state().groups["default"].parent, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Subscribed clients receive the events of the tasks they follow.
+async fn test_mock_daemon_subscribe() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+    client.add(helper::get_add_message("default")).await?;
+
+    let mut subscriber = Client::connect(daemon.settings()).await?;
+    let mut events = subscriber
+        .subscribe(SubscribeMessage {
+            task_ids: vec![1],
+            ..Default::default()
+        })
+        .await?;
+
+    // Only the events of the second task are sent.
+    client.add(helper::get_add_message("default")).await?;
+    daemon.set_status(0, TaskStatus::Running)?;
+    daemon.set_status(1, TaskStatus::Running)?;
+
+    match events.next().await? {
+        Some(Event::TaskAdded(task)) => assert_eq!(task.id, 1),
+        event => panic!("Expected added task, got {:?}", event),
+    }
+    assert_eq!(
+        events.next().await?,
+        Some(Event::TaskStatusChanged {
+            task_id: 1,
+            group: "default".into(),
+            previous_status: TaskStatus::Queued,
+            status: TaskStatus::Running,
+        })
+    );
+
+    // Group events aren't filtered by task ids.
+    client
+        .send_request(Message::Group(GroupMessage::Add {
+            name: "other".into(),
+            parallel_tasks: None,
+            time_windows: None,
+            ordering: None,
+            parent: None,
+        }))
+        .await?;
+    match events.next().await? {
+        Some(Event::GroupChanged { name, group }) => {
+            assert_eq!(name, "other");
+            assert!(group.is_some());
+        }
+        event => panic!("Expected changed group, got {:?}", event),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Atomic batches are rolled back completely, if one of their messages fails.
+async fn test_mock_daemon_batch() -> Result<()> {
+    better_panic::install();
+    let daemon = MockDaemon::start().await?;
+    let mut client = Client::connect(daemon.settings()).await?;
+
+    let mut dependent = helper::get_add_message("default");
+    dependent.batch_dependencies = vec![0];
+    let messages = vec![
+        Message::Add(helper::get_add_message("default")),
+        Message::Add(dependent),
+        Message::Add(helper::get_add_message("unknown")),
+    ];
+    let codes = |response: Message| match response {
+        Message::BatchResponse(responses) => responses
+            .into_iter()
+            .map(|response| match response {
+                Message::StructuredFailure(failure) => Some(failure.code),
+                _ => None,
+            })
+            .collect::<Vec<Option<ErrorCode>>>(),
+        response => panic!("Expected batch response, got {:?}", response),
+    };
+
+    let response = client
+        .send_request(Message::Batch(BatchMessage {
+            messages: messages.clone(),
+            atomic: true,
+        }))
+        .await?;
+    assert_eq!(
+        codes(response),
+        vec![
+            Some(ErrorCode::InvalidRequest),
+            Some(ErrorCode::InvalidRequest),
+            Some(ErrorCode::GroupNotFound),
+        ]
+    );
+    assert!(daemon.state().tasks.is_empty());
+
+    // Without atomicity, all messages that can be applied are.
+    let response = client
+        .send_request(Message::Batch(BatchMessage {
+            messages,
+            atomic: false,
+        }))
+        .await?;
+    assert_eq!(
+        codes(response),
+        vec![None, None, Some(ErrorCode::GroupNotFound)]
+    );
+    let state = daemon.state();
+    assert_eq!(state.tasks.len(), 2);
+    assert_eq!(state.tasks[&1].dependencies, vec![0]);
+
+    // Invalid batches aren't handled at all.
+    let mut invalid = helper::get_add_message("default");
+    invalid.batch_dependencies = vec![1];
+    let response = client
+        .send_request(Message::Batch(BatchMessage {
+            messages: vec![Message::Add(invalid)],
+            atomic: false,
+        }))
+        .await;
+    assert_eq!(failure_code(response), ErrorCode::InvalidRequest);
+
+    Ok(())
+}
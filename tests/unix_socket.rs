@@ -1,3 +1,4 @@
+#![cfg(feature = "async")]
 use anyhow::Result;
 use pretty_assertions::assert_eq;
 use serde_cbor::de::from_slice;